@@ -0,0 +1,56 @@
+//! Reads one large body through `Response::chunks` and through
+//! `Response::as_bytes`, served from a local socket so the network doesn't
+//! drown the difference. Run with `cargo bench`.
+
+#[cfg(windows)]
+fn main() {
+    use std::io::{Read, Write};
+    use std::time::{Duration, Instant};
+    use thin_http::wininet;
+
+    const BODY_SIZE: usize = 16 * 1024 * 1024;
+    const ROUNDS: u32 = 5;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        let body = vec![b'x'; BODY_SIZE];
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = [0u8; 4096];
+            let _ = stream.read(&mut request).unwrap();
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nCache-Control: no-store\r\n\
+                 Connection: close\r\n\r\n",
+                BODY_SIZE
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(&body).unwrap();
+        }
+    });
+
+    let internet = wininet::Internet::open("bench", None).unwrap();
+    let url = format!("http://127.0.0.1:{}/", port);
+    let time = |read: &dyn Fn(&wininet::Response) -> usize| {
+        let mut total = Duration::default();
+        for _ in 0..ROUNDS {
+            let response = internet.get(&url, None).unwrap();
+            let start = Instant::now();
+            assert_eq!(read(&response), BODY_SIZE);
+            total += start.elapsed();
+        }
+        total / ROUNDS
+    };
+    let chunks = time(&|response| {
+        response
+            .chunks(64 * 1024)
+            .map(|chunk| chunk.unwrap().len())
+            .sum()
+    });
+    let bytes = time(&|response| response.as_bytes().count());
+    println!("chunks(64 KiB): {:?} per {} MiB", chunks, BODY_SIZE >> 20);
+    println!("as_bytes():     {:?} per {} MiB", bytes, BODY_SIZE >> 20);
+}
+
+#[cfg(not(windows))]
+fn main() {}
@@ -1,23 +1,68 @@
-#![cfg(windows)]
+//! An HTTP client over WinINet. Off Windows only the platform-neutral `uri`,
+//! `multipart` and `chunked` helpers are built, so a cross-platform crate can
+//! depend on this one and gate its own use of `wininet`.
+
+#[cfg(windows)]
 extern crate winapi;
 
-mod wide_string {
+/// NUL-terminated UTF-16 strings for passing to `W` Win32 functions.
+#[cfg(windows)]
+pub mod wide_string {
 
-    use std::convert::From;
+    use std::convert::{From, TryFrom};
     use std::ffi::OsStr;
+    use std::fmt;
     use std::ops::Deref;
     use std::os::windows::prelude::*;
 
+    #[derive(Debug, Clone, PartialEq, Eq)]
     pub struct WideString(Vec<u16>);
 
+    impl WideString {
+        /// Like `From<&str>`, but refuses input with an embedded NUL, which
+        /// Win32 would silently treat as the end of the string.
+        pub fn try_new(s: &str) -> Result<Self, InteriorNulError> {
+            match s.find('\0') {
+                Some(position) => Err(InteriorNulError { position }),
+                None => Ok(WideString::from(s)),
+            }
+        }
+
+        /// Decodes up to the terminator, replacing unpaired surrogates.
+        pub fn to_string_lossy(&self) -> String {
+            String::from_utf16_lossy(&self.0[..self.0.len() - 1])
+        }
+    }
+
+    /// The `From` conversions copy interior NULs through unchanged, so
+    /// Win32 sees only the text before the first one. Use `try_new` for
+    /// untrusted input.
+    impl From<&OsStr> for WideString {
+        fn from(s: &OsStr) -> Self {
+            WideString(s.encode_wide().chain(Some(0)).collect::<Vec<_>>())
+        }
+    }
+
     impl From<&str> for WideString {
         fn from(s: &str) -> Self {
-            WideString(
-                OsStr::new(s)
-                    .encode_wide()
-                    .chain(Some(0).into_iter())
-                    .collect::<Vec<_>>(),
-            )
+            WideString::from(OsStr::new(s))
+        }
+    }
+
+    impl From<String> for WideString {
+        fn from(s: String) -> Self {
+            WideString::from(s.as_str())
+        }
+    }
+
+    /// Takes a buffer filled in by a Win32 function, keeping everything up to
+    /// the first NUL. Fails if the buffer has no terminator.
+    impl TryFrom<&[u16]> for WideString {
+        type Error = MissingNulError;
+
+        fn try_from(buffer: &[u16]) -> Result<Self, Self::Error> {
+            let end = buffer.iter().position(|&c| c == 0).ok_or(MissingNulError)?;
+            Ok(WideString(buffer[..=end].to_vec()))
         }
     }
 
@@ -28,211 +73,6855 @@ mod wide_string {
             &self.0
         }
     }
-}
 
-pub mod wininet {
-    use super::wide_string::WideString;
-    use std::convert::From;
-    use std::iter::Iterator;
-    use std::ptr::null;
-    use winapi::shared::minwindef::DWORD;
-    use winapi::um::wininet::InternetReadFile;
-    use winapi::um::wininet::HTTP_QUERY_STATUS_CODE;
-    use winapi::um::wininet::*;
+    #[derive(Debug, PartialEq, Eq)]
+    pub struct MissingNulError;
 
-    #[derive(Debug)]
-    pub struct Internet(HINTERNET);
+    impl fmt::Display for MissingNulError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "wide string buffer has no NUL terminator")
+        }
+    }
 
-    impl Drop for Internet {
-        fn drop(&mut self) {
-            unsafe {
-                winapi::um::wininet::InternetCloseHandle(self.0);
-            }
+    impl std::error::Error for MissingNulError {}
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub struct InteriorNulError {
+        /// Byte offset of the first NUL in the input.
+        pub position: usize,
+    }
+
+    impl fmt::Display for InteriorNulError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "string contains a NUL at byte {}", self.position)
         }
     }
 
-    #[allow(dead_code)]
-    enum OpenType {
-        Proxy = winapi::um::wininet::INTERNET_OPEN_TYPE_PROXY as isize,
-        Direct = winapi::um::wininet::INTERNET_OPEN_TYPE_DIRECT as isize,
-        Preconfig = winapi::um::wininet::INTERNET_OPEN_TYPE_PRECONFIG as isize,
-        PreconfigWithNoAutoproxy =
-            winapi::um::wininet::INTERNET_OPEN_TYPE_PRECONFIG_WITH_NO_AUTOPROXY as isize,
+    impl std::error::Error for InteriorNulError {}
+}
+
+pub mod uri {
+    use std::fmt::Write;
+
+    /// Percent-encodes everything except RFC 3986 unreserved characters
+    /// (`A-Z a-z 0-9 - . _ ~`), so the result is safe as a path segment or
+    /// query component.
+    pub fn encode_uri_component(s: &str) -> String {
+        let mut encoded = String::with_capacity(s.len());
+        for &byte in s.as_bytes() {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    encoded.push(byte as char)
+                }
+                _ => write!(encoded, "%{:02X}", byte).unwrap(),
+            }
+        }
+        encoded
     }
 
-    #[allow(dead_code)]
-    enum InternetFlag {
-        None = 0,
-        Offline = winapi::um::wininet::INTERNET_FLAG_OFFLINE as isize,
-        Async = winapi::um::wininet::INTERNET_FLAG_ASYNC as isize,
-        OfflineAndAsync = winapi::um::wininet::INTERNET_FLAG_OFFLINE as isize
-            | winapi::um::wininet::INTERNET_FLAG_ASYNC as isize,
+    /// Builds an `a=1&b=two%20words` query string from key/value pairs.
+    #[derive(Debug, Default, Clone)]
+    pub struct QueryBuilder {
+        query: String,
     }
 
-    impl From<OpenType> for DWORD {
-        fn from(w: OpenType) -> Self {
-            w as DWORD
+    impl QueryBuilder {
+        pub fn new() -> QueryBuilder {
+            QueryBuilder::default()
+        }
+
+        pub fn pair(mut self, key: &str, value: &str) -> Self {
+            if !self.query.is_empty() {
+                self.query.push('&');
+            }
+            self.query.push_str(&encode_uri_component(key));
+            self.query.push('=');
+            self.query.push_str(&encode_uri_component(value));
+            self
+        }
+
+        pub fn build(&self) -> String {
+            self.query.clone()
         }
     }
+}
 
-    impl From<InternetFlag> for DWORD {
-        fn from(w: InternetFlag) -> Self {
-            w as DWORD
+pub mod multipart {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    /// A `multipart/form-data` body made of text fields and file parts.
+    #[derive(Debug, Clone)]
+    pub struct Multipart {
+        boundary: String,
+        body: Vec<u8>,
+    }
+
+    impl Default for Multipart {
+        fn default() -> Self {
+            Multipart::new()
         }
     }
 
-    pub struct Response(HINTERNET);
+    impl Multipart {
+        pub fn new() -> Multipart {
+            // RandomState is seeded per process, which is plenty to keep the
+            // boundary from turning up in the content by accident.
+            let random = || RandomState::new().build_hasher().finish();
+            Multipart {
+                boundary: format!("thin-http-{:016x}{:016x}", random(), random()),
+                body: Vec::new(),
+            }
+        }
 
-    impl Drop for Response {
-        fn drop(&mut self) {
-            unsafe {
-                winapi::um::wininet::InternetCloseHandle(self.0);
+        pub fn add_text(self, name: &str, value: &str) -> Self {
+            self.add_part(name, None, None, value.as_bytes())
+        }
+
+        pub fn add_file(
+            self,
+            name: &str,
+            filename: &str,
+            content_type: &str,
+            bytes: &[u8],
+        ) -> Self {
+            self.add_part(name, Some(filename), Some(content_type), bytes)
+        }
+
+        fn add_part(
+            mut self,
+            name: &str,
+            filename: Option<&str>,
+            content_type: Option<&str>,
+            bytes: &[u8],
+        ) -> Self {
+            let mut head = format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"",
+                self.boundary,
+                escape(name)
+            );
+            if let Some(filename) = filename {
+                head.push_str(&format!("; filename=\"{}\"", escape(filename)));
             }
+            head.push_str("\r\n");
+            if let Some(content_type) = content_type {
+                head.push_str(&format!("Content-Type: {}\r\n", content_type));
+            }
+            head.push_str("\r\n");
+            self.body.extend_from_slice(head.as_bytes());
+            self.body.extend_from_slice(bytes);
+            self.body.extend_from_slice(b"\r\n");
+            self
+        }
+
+        pub fn boundary(&self) -> &str {
+            &self.boundary
+        }
+
+        /// The `Content-Type` header value, boundary included.
+        pub fn content_type(&self) -> String {
+            format!("multipart/form-data; boundary={}", self.boundary)
+        }
+
+        pub fn content_length(&self) -> u64 {
+            (self.body.len() + self.boundary.len() + 6) as u64
+        }
+
+        /// The finished body, closing delimiter included.
+        pub fn into_body(mut self) -> Vec<u8> {
+            self.body.extend_from_slice(b"--");
+            self.body.extend_from_slice(self.boundary.as_bytes());
+            self.body.extend_from_slice(b"--\r\n");
+            self.body
         }
     }
 
-    impl Internet {
-        pub fn open(agent: &str, proxy: Option<&str>) -> Option<Internet> {
-            let agent = WideString::from(agent);
+    // Same escaping browsers apply to field names and filenames.
+    fn escape(value: &str) -> String {
+        value
+            .replace('"', "%22")
+            .replace('\r', "%0D")
+            .replace('\n', "%0A")
+    }
+}
 
-            let internet_handle = unsafe {
-                InternetOpenW(
-                    agent.as_ptr(),
-                    match proxy {
-                        Some(_) => OpenType::Proxy.into(),
-                        None => OpenType::Direct.into(),
-                    },
-                    match proxy {
-                        Some(&ref proxyname) => WideString::from(proxyname).as_ptr(),
-                        None => null(),
-                    },
-                    null(),
-                    InternetFlag::None.into(),
-                )
-            };
+/// Readers that undo `Transfer-Encoding: chunked` for bodies that reach the
+/// caller still framed.
+pub mod chunked {
+    use std::io::{self, BufRead, Read};
 
-            if internet_handle.is_null() {
-                None
-            } else {
-                Some(Internet(internet_handle))
+    // Longest size or trailer line accepted before giving up on the stream.
+    const MAX_LINE: u64 = 8192;
+
+    enum State {
+        Size,
+        Data(u64),
+        Done,
+    }
+
+    /// Yields the chunk data of `inner` back to back. Chunk extensions are
+    /// skipped; the trailer fields after the last chunk are kept for
+    /// `trailers`.
+    pub struct Decoder<R> {
+        inner: R,
+        state: State,
+        trailers: Vec<(String, String)>,
+    }
+
+    impl<R: BufRead> Decoder<R> {
+        pub fn new(inner: R) -> Decoder<R> {
+            Decoder {
+                inner,
+                state: State::Size,
+                trailers: Vec::new(),
             }
         }
 
-        pub fn get(&self, url: &str, headers: Option<&str>) -> Option<Response> {
-            let handle = unsafe {
-                InternetOpenUrlW(
-                    self.0,
-                    WideString::from(url).as_ptr(),
-                    match headers {
-                        Some(&ref headers) => WideString::from(headers).as_ptr(),
-                        None => null(),
+        /// Whether the last chunk and the trailers have been read.
+        pub fn is_done(&self) -> bool {
+            matches!(self.state, State::Done)
+        }
+
+        /// The trailer fields, in order. Empty until `is_done`.
+        pub fn trailers(&self) -> &[(String, String)] {
+            &self.trailers
+        }
+
+        pub fn into_inner(self) -> R {
+            self.inner
+        }
+
+        // One line without its CRLF or bare LF.
+        fn read_line(&mut self) -> io::Result<String> {
+            let mut line = Vec::new();
+            (&mut self.inner)
+                .take(MAX_LINE)
+                .read_until(b'\n', &mut line)?;
+            if line.last() != Some(&b'\n') {
+                return Err(if line.len() as u64 == MAX_LINE {
+                    invalid("chunked line too long")
+                } else {
+                    io::ErrorKind::UnexpectedEof.into()
+                });
+            }
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            String::from_utf8(line).map_err(|_| invalid("chunked line is not UTF-8"))
+        }
+
+        fn read_size(&mut self) -> io::Result<u64> {
+            let line = self.read_line()?;
+            let size = line.split(';').next().unwrap_or("").trim();
+            if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(invalid("bad chunk size"));
+            }
+            u64::from_str_radix(size, 16).map_err(|_| invalid("chunk size too large"))
+        }
+
+        fn read_trailers(&mut self) -> io::Result<()> {
+            loop {
+                let line = self.read_line()?;
+                if line.is_empty() {
+                    return Ok(());
+                }
+                if line.starts_with([' ', '\t']) {
+                    // An obsolete continuation of the previous field.
+                    match self.trailers.last_mut() {
+                        Some((_, value)) => {
+                            value.push(' ');
+                            value.push_str(line.trim());
+                        }
+                        None => return Err(invalid("trailer continues nothing")),
+                    }
+                    continue;
+                }
+                match line.split_once(':') {
+                    Some((name, value)) => self
+                        .trailers
+                        .push((name.trim().to_string(), value.trim().to_string())),
+                    None => return Err(invalid("trailer line has no colon")),
+                }
+            }
+        }
+    }
+
+    impl<R: BufRead> Read for Decoder<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            loop {
+                match self.state {
+                    State::Done => return Ok(0),
+                    State::Size => match self.read_size()? {
+                        0 => {
+                            self.read_trailers()?;
+                            self.state = State::Done;
+                        }
+                        size => self.state = State::Data(size),
                     },
-                    0xFFFFFFFF,
-                    INTERNET_FLAG_RELOAD
-                        | INTERNET_FLAG_DONT_CACHE
-                        | INTERNET_FLAG_RAW_DATA
-                        | INTERNET_FLAG_SECURE,
-                    0,
-                )
-            };
-            if handle.is_null() {
-                None
+                    State::Data(left) => {
+                        if buf.is_empty() {
+                            return Ok(0);
+                        }
+                        let max = left.min(buf.len() as u64) as usize;
+                        let read = self.inner.read(&mut buf[..max])?;
+                        if read == 0 {
+                            return Err(io::ErrorKind::UnexpectedEof.into());
+                        }
+                        let left = left - read as u64;
+                        if left == 0 {
+                            if !self.read_line()?.is_empty() {
+                                return Err(invalid("chunk data overruns its size"));
+                            }
+                            self.state = State::Size;
+                        } else {
+                            self.state = State::Data(left);
+                        }
+                        return Ok(read);
+                    }
+                }
+            }
+        }
+    }
+
+    fn invalid(message: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, message)
+    }
+
+    /// Whether a body read from WinINet still carries chunked framing. That
+    /// is only so when it was requested with `INTERNET_FLAG_RAW_DATA` and
+    /// sent with `Transfer-Encoding: chunked`; otherwise WinINet has already
+    /// removed the framing, and the body must not be decoded again.
+    pub fn is_framed(raw_data: bool, transfer_encoding: Option<&str>) -> bool {
+        raw_data
+            && transfer_encoding
+                .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"))
+    }
+
+    /// A body as the caller should see it: `Decoder` output when it is
+    /// framed, otherwise the bytes of `inner` unchanged.
+    pub enum Reader<R> {
+        Framed(Decoder<io::BufReader<R>>),
+        Plain(R),
+    }
+
+    impl<R: Read> Reader<R> {
+        pub fn new(inner: R, framed: bool) -> Reader<R> {
+            if framed {
+                Reader::Framed(Decoder::new(io::BufReader::new(inner)))
             } else {
-                Some(Response(handle))
+                Reader::Plain(inner)
+            }
+        }
+
+        /// The trailer fields of a framed body once it has been read to the
+        /// end, otherwise empty.
+        pub fn trailers(&self) -> &[(String, String)] {
+            match self {
+                Reader::Framed(decoder) => decoder.trailers(),
+                Reader::Plain(_) => &[],
+            }
+        }
+    }
+
+    impl<R: Read> Read for Reader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self {
+                Reader::Framed(decoder) => decoder.read(buf),
+                Reader::Plain(plain) => plain.read(buf),
             }
         }
     }
+}
+
+/// Standard, padded base64 (RFC 4648), for `Authorization` headers.
+// Only `wininet` and the tests use it.
+#[cfg_attr(not(windows), allow(dead_code))]
+mod base64 {
+    pub fn encode(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8; 64] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+        for chunk in bytes.chunks(3) {
+            let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+                group | (byte as u32) << (16 - 8 * i)
+            });
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3F) as usize] as char);
+                } else {
+                    encoded.push('=');
+                }
+            }
+        }
+        encoded
+    }
+}
 
-    const BUFFER_SIZE: DWORD = 1000;
+#[cfg(windows)]
+mod http_date {
+    use std::convert::TryFrom;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-    pub struct Bytes<'a> {
-        handle: &'a Response,
-        data: [u8; BUFFER_SIZE as usize],
-        index: DWORD,
-        size: DWORD,
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    /// Formats `time` as an RFC 1123 date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+    /// Times before 1970 are clamped to the epoch.
+    pub fn format(time: SystemTime) -> String {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs();
+        let days = secs / 86400;
+        let (year, month, day) = civil_from_days(days as i64);
+        format!(
+            "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+            DAYS[(days % 7) as usize],
+            day,
+            MONTHS[month as usize - 1],
+            year,
+            secs % 86400 / 3600,
+            secs % 3600 / 60,
+            secs % 60
+        )
     }
 
-    impl<'a> Bytes<'a> {
-        fn new(handle: &'a Response) -> Bytes<'a> {
-            Bytes {
-                handle: handle,
-                data: [0; BUFFER_SIZE as usize],
-                index: 0,
-                size: 0,
+    /// Parses an HTTP date in RFC 1123 form or either legacy form the spec
+    /// still requires accepting: RFC 850 (`Sunday, 06-Nov-94 08:49:37 GMT`)
+    /// and asctime (`Sun Nov  6 08:49:37 1994`).
+    pub fn parse(value: &str) -> Option<SystemTime> {
+        let normalized = value.replace([',', '-'], " ");
+        let tokens: Vec<&str> = normalized.split_whitespace().collect();
+        let (day, month, year, time) = match tokens.as_slice() {
+            [_, month, day, time, year] if month_number(month).is_some() => {
+                (day, month, year, time)
+            }
+            [_, day, month, year, time, zone] if zone.eq_ignore_ascii_case("GMT") => {
+                (day, month, year, time)
             }
+            _ => return None,
+        };
+        let day: u32 = day.parse().ok()?;
+        let month = month_number(month)?;
+        let mut year: i64 = year.parse().ok()?;
+        if year < 100 {
+            // RFC 850's two-digit years.
+            year += if year < 70 { 2000 } else { 1900 };
         }
+        let mut time = time.split(':').map(|part| part.parse::<u64>().ok());
+        let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+        if time.next().is_some()
+            || !(1..=31).contains(&day)
+            || hour > 23
+            || minute > 59
+            || second > 60
+        {
+            return None;
+        }
+        let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+        Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + hour * 3600 + minute * 60 + second))
     }
 
-    impl<'a> Iterator for Bytes<'a> {
-        type Item = u8;
-        fn next(&mut self) -> Option<Self::Item> {
-            let mut result = 0;
-            let mut read_size: DWORD = 0;
-            if self.index >= self.size {
-                unsafe {
-                    result = InternetReadFile(
-                        self.handle.0,
-                        (&mut self.data[..]).as_mut_ptr() as *mut winapi::ctypes::c_void,
-                        BUFFER_SIZE,
-                        &mut read_size as *mut DWORD,
-                    );
-                    self.size = read_size;
-                    self.index = 0;
-                };
-            };
-            let return_value = if (result != 0) && (read_size == 0) {
-                None
+    fn month_number(name: &str) -> Option<u32> {
+        MONTHS
+            .iter()
+            .position(|month| month.eq_ignore_ascii_case(name))
+            .map(|index| index as u32 + 1)
+    }
+
+    fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let mp = i64::from((month + 9) % 12);
+        let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146097 + doe - 719468
+    }
+
+    // Howard Hinnant's algorithm for proleptic Gregorian dates.
+    fn civil_from_days(days: i64) -> (i64, u32, u32) {
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        (year, month, day)
+    }
+}
+
+#[cfg(windows)]
+pub mod wininet {
+    use super::base64;
+    use super::chunked;
+    use super::http_date;
+    use super::multipart::Multipart;
+    use super::uri::QueryBuilder;
+    use super::wide_string::WideString;
+    use std::borrow::Cow;
+    use std::collections::hash_map::RandomState;
+    use std::collections::HashMap;
+    use std::convert::{From, TryFrom};
+    use std::fmt;
+    use std::fs::{self, File, OpenOptions};
+    use std::hash::{BuildHasher, Hasher};
+    use std::io::{self, Write};
+    use std::iter::Iterator;
+    use std::path::Path;
+    use std::ptr::{null, null_mut};
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex, OnceLock};
+    use std::time::{Duration, Instant, SystemTime};
+    use winapi::ctypes::c_int;
+    use winapi::shared::minwindef::{BOOL, DWORD, UINT};
+    use winapi::shared::ntdef::{HANDLE, LPCSTR, LPCWSTR, LPWSTR};
+    use winapi::shared::winerror::{ERROR_INSUFFICIENT_BUFFER, ERROR_NO_MORE_ITEMS};
+    use winapi::um::errhandlingapi::GetLastError;
+    use winapi::um::libloaderapi::{GetModuleHandleW, GetProcAddress};
+    use winapi::um::schannel::{
+        SecPkgContext_CipherInfo, SecPkgContext_ConnectionInfo, SECPKGCONTEXT_CIPHERINFO_V1,
+    };
+    use winapi::um::stringapiset::MultiByteToWideChar;
+    use winapi::um::winbase::{
+        FormatMessageW, FORMAT_MESSAGE_FROM_HMODULE, FORMAT_MESSAGE_FROM_SYSTEM,
+        FORMAT_MESSAGE_IGNORE_INSERTS,
+    };
+    use winapi::um::wincrypt::{
+        CertCloseStore, CertDuplicateCertificateContext, CertFindCertificateInStore,
+        CertFreeCertificateContext, CertOpenSystemStoreW, CryptAcquireContextW, CryptCreateHash,
+        CryptDestroyHash, CryptGetHashParam, CryptHashData, CryptReleaseContext, ALG_ID, CALG_MD5,
+        CALG_SHA_256, CERT_CONTEXT, CERT_FIND_HASH, CRYPT_HASH_BLOB, CRYPT_VERIFYCONTEXT,
+        HCRYPTHASH, HCRYPTPROV, HP_HASHVAL, PCCERT_CONTEXT, PKCS_7_ASN_ENCODING, PROV_RSA_AES,
+        X509_ASN_ENCODING,
+    };
+    use winapi::um::wininet::InternetReadFile;
+    use winapi::um::wininet::HTTP_QUERY_STATUS_CODE;
+    use winapi::um::wininet::*;
+    use winapi::um::winnt::{LONG, RTL_OSVERSIONINFOW};
+
+    #[derive(Debug)]
+    pub enum HttpError {
+        Win32 {
+            code: DWORD,
+            message: String,
+            /// Extra text from `InternetGetLastResponseInfo`, if WinINet left any.
+            extended: Option<String>,
+        },
+        Io(io::Error),
+        /// The response was aborted with `Response::cancel`.
+        Cancelled,
+        /// A URL, header or other argument was refused before reaching WinINet.
+        InvalidInput(String),
+        /// A 4xx or 5xx status turned into an error by `error_for_status`,
+        /// with the start of the body for context.
+        Status {
+            status: u16,
+            body: String,
+        },
+        /// A 407 turned into an error by `error_for_status`, with the
+        /// proxy's `Proxy-Authenticate` challenges. Supply credentials with
+        /// `Internet::set_proxy_credentials` and send the request again.
+        ProxyAuthRequired {
+            challenges: Vec<String>,
+        },
+        /// `max_redirects` was exceeded or a redirect led back to a URL
+        /// already visited. `urls` is the chain, starting with the request.
+        TooManyRedirects {
+            urls: Vec<String>,
+        },
+        #[cfg(feature = "json")]
+        Json(serde_json::Error),
+    }
+
+    impl HttpError {
+        fn last_error() -> HttpError {
+            let code = unsafe { GetLastError() };
+            let extended = if (INTERNET_ERROR_BASE..=INTERNET_ERROR_LAST).contains(&code) {
+                last_response_info()
             } else {
-                Some(self.data[self.index as usize])
+                None
             };
-            self.index += 1;
-            return_value
+            HttpError::Win32 {
+                code,
+                message: format_message(code),
+                extended,
+            }
+        }
+
+        fn from_code(code: DWORD) -> HttpError {
+            HttpError::Win32 {
+                code,
+                message: format_message(code),
+                extended: None,
+            }
+        }
+
+        /// The Win32 error code, e.g. `ERROR_INTERNET_TIMEOUT`, or 0 for
+        /// errors that didn't come from Win32.
+        pub fn code(&self) -> u32 {
+            match self {
+                HttpError::Win32 { code, .. } => *code,
+                HttpError::Io(error) => error.raw_os_error().unwrap_or(0) as u32,
+                HttpError::Cancelled => ERROR_INTERNET_OPERATION_CANCELLED,
+                HttpError::InvalidInput(_)
+                | HttpError::Status { .. }
+                | HttpError::ProxyAuthRequired { .. }
+                | HttpError::TooManyRedirects { .. } => 0,
+                #[cfg(feature = "json")]
+                HttpError::Json(_) => 0,
+            }
+        }
+
+        /// The HTTP status for `Status` and `ProxyAuthRequired` errors.
+        pub fn status(&self) -> Option<u16> {
+            match self {
+                HttpError::Status { status, .. } => Some(*status),
+                HttpError::ProxyAuthRequired { .. } => Some(407),
+                _ => None,
+            }
+        }
+
+        pub fn message(&self) -> String {
+            match self {
+                HttpError::Win32 { message, .. } => message.clone(),
+                error => error.to_string(),
+            }
         }
     }
 
-    impl Response {
-        pub fn as_bytes(&self) -> Bytes {
-            Bytes::new(&self)
+    impl fmt::Display for HttpError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                HttpError::Win32 {
+                    code,
+                    message,
+                    extended: Some(extended),
+                } => write!(f, "{}: {} (error {})", message, extended, code),
+                HttpError::Win32 { code, message, .. } => write!(f, "{} (error {})", message, code),
+                HttpError::Io(error) => error.fmt(f),
+                HttpError::Cancelled => write!(f, "the request was cancelled"),
+                HttpError::InvalidInput(message) => write!(f, "invalid input: {}", message),
+                HttpError::Status { status, body } if body.is_empty() => {
+                    write!(f, "HTTP status {}", status)
+                }
+                HttpError::Status { status, body } => write!(f, "HTTP status {}: {}", status, body),
+                HttpError::ProxyAuthRequired { challenges } if challenges.is_empty() => {
+                    write!(f, "proxy authentication required")
+                }
+                HttpError::ProxyAuthRequired { challenges } => {
+                    write!(
+                        f,
+                        "proxy authentication required: {}",
+                        challenges.join(", ")
+                    )
+                }
+                HttpError::TooManyRedirects { urls } => {
+                    write!(f, "too many redirects: {}", urls.join(" -> "))
+                }
+                #[cfg(feature = "json")]
+                HttpError::Json(error) => error.fmt(f),
+            }
         }
+    }
 
-        pub fn body(&self) -> Vec<u8> {
-            self.as_bytes().collect()
+    impl std::error::Error for HttpError {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            match self {
+                HttpError::Io(error) => Some(error),
+                #[cfg(feature = "json")]
+                HttpError::Json(error) => Some(error),
+                _ => None,
+            }
         }
+    }
 
-        pub fn body_as_string(self) -> Result<String, std::string::FromUtf8Error> {
-            String::from_utf8(self.body())
+    impl From<io::Error> for HttpError {
+        fn from(error: io::Error) -> Self {
+            // Unwrap errors such as `Cancelled` that went through an io::Result.
+            if !error.get_ref().is_some_and(|inner| inner.is::<HttpError>()) {
+                return HttpError::Io(error);
+            }
+            match error
+                .into_inner()
+                .map(|inner| inner.downcast::<HttpError>())
+            {
+                Some(Ok(inner)) => *inner,
+                _ => unreachable!(),
+            }
         }
+    }
 
-        pub fn status(&self) -> DWORD {
-            let mut status_code: DWORD = 0;
-            let mut len: DWORD = 4;
-            let mut index: DWORD = 0;
-            unsafe {
-                HttpQueryInfoW(
-                    self.0,
-                    HTTP_QUERY_STATUS_CODE | HTTP_QUERY_FLAG_NUMBER,
-                    (&mut status_code as *mut DWORD) as *mut winapi::ctypes::c_void,
-                    &mut len as *mut DWORD,
-                    &mut index as *mut DWORD,
-                );
+    #[cfg(feature = "json")]
+    impl From<serde_json::Error> for HttpError {
+        fn from(error: serde_json::Error) -> Self {
+            HttpError::Json(error)
+        }
+    }
+
+    /// Converts `s` for a WinINet call, refusing interior NULs that would cut
+    /// it short. `what` names the argument in the error.
+    fn wide(s: &str, what: &str) -> Result<WideString, HttpError> {
+        WideString::try_new(s)
+            .map_err(|error| HttpError::InvalidInput(format!("{} {}", what, error)))
+    }
+
+    /// Whether `url` names the `https` scheme, which alone decides
+    /// `INTERNET_FLAG_SECURE`.
+    fn is_https(url: &str) -> bool {
+        url.get(..6)
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case("https:"))
+    }
+
+    fn read_raw(handle: &Handle, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(error) = handle.closed_error() {
+            return Err(io::Error::other(error));
+        }
+        // InternetReadFile waits until `buf` is full, so ask how much has
+        // already arrived and read only that. This keeps a slow stream
+        // flowing piece by piece instead of stalling on a large buffer.
+        let mut available: DWORD = 0;
+        let mut result = unsafe { InternetQueryDataAvailable(handle.raw, &mut available, 0, 0) };
+        let mut read_size: DWORD = 0;
+        if result != 0 && available > 0 && !buf.is_empty() {
+            result = unsafe {
+                InternetReadFile(
+                    handle.raw,
+                    buf.as_mut_ptr() as *mut winapi::ctypes::c_void,
+                    buf.len().min(available as usize) as DWORD,
+                    &mut read_size,
+                )
             };
-            status_code
+        }
+        if result == 0 {
+            if let Some(error) = handle.closed_error() {
+                Err(io::Error::other(error))
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        } else {
+            Ok(read_size as usize)
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::wininet;
+    fn format_message(code: DWORD) -> String {
+        // WinINet's messages live in wininet.dll rather than the system table.
+        let module = if (INTERNET_ERROR_BASE..=INTERNET_ERROR_LAST).contains(&code) {
+            unsafe { GetModuleHandleW(WideString::from("wininet.dll").as_ptr()) }
+        } else {
+            null_mut()
+        };
+        let mut buffer = [0u16; 512];
+        let len = unsafe {
+            FormatMessageW(
+                FORMAT_MESSAGE_IGNORE_INSERTS
+                    | if module.is_null() {
+                        FORMAT_MESSAGE_FROM_SYSTEM
+                    } else {
+                        FORMAT_MESSAGE_FROM_HMODULE
+                    },
+                module as *const winapi::ctypes::c_void,
+                code,
+                0,
+                buffer.as_mut_ptr(),
+                buffer.len() as DWORD,
+                null_mut(),
+            )
+        };
+        String::from_utf16_lossy(&buffer[..len as usize])
+            .trim_end()
+            .to_string()
+    }
 
-    #[test]
-    fn it_works() {
-        let internet = wininet::Internet::open("agent", None).unwrap();
-        let response = internet.get("http://example.com/", None).unwrap();
-        assert_eq!(response.status(), 200);
-        assert!(String::from_utf8(response.body())
-            .unwrap()
-            .find("<h1>Example Domain</h1>")
-            .is_some());
+    fn last_response_info() -> Option<String> {
+        let mut error: DWORD = 0;
+        let mut buffer = vec![0u16; 256];
+        loop {
+            let mut len = buffer.len() as DWORD;
+            let ok =
+                unsafe { InternetGetLastResponseInfoW(&mut error, buffer.as_mut_ptr(), &mut len) };
+            if ok != 0 {
+                let text = String::from_utf16_lossy(&buffer[..len as usize]);
+                let text = text.trim();
+                return if text.is_empty() {
+                    None
+                } else {
+                    Some(text.to_string())
+                };
+            }
+            if unsafe { GetLastError() } != ERROR_INSUFFICIENT_BUFFER {
+                return None;
+            }
+            // `len` now holds the required size, without the terminator.
+            buffer.resize(len as usize + 1, 0);
+        }
+    }
+
+    #[derive(Debug)]
+    struct Handle {
+        raw: HINTERNET,
+        closed: AtomicBool,
+        // Set when a deadline watchdog, rather than `cancel`, closed it.
+        expired: AtomicBool,
+        // Keeps the handle this one was opened from alive until after it is
+        // closed, since WinINet handles must not outlive their parents.
+        _parent: Option<Arc<Handle>>,
+    }
+
+    impl Handle {
+        fn new(raw: HINTERNET, parent: Option<Arc<Handle>>) -> Result<Handle, HttpError> {
+            if raw.is_null() {
+                Err(HttpError::last_error())
+            } else {
+                Ok(Handle {
+                    raw,
+                    closed: AtomicBool::new(false),
+                    expired: AtomicBool::new(false),
+                    _parent: parent,
+                })
+            }
+        }
+
+        fn close(&self) {
+            if !self.closed.swap(true, Ordering::SeqCst) {
+                unsafe {
+                    winapi::um::wininet::InternetCloseHandle(self.raw);
+                }
+            }
+        }
+
+        fn is_closed(&self) -> bool {
+            self.closed.load(Ordering::SeqCst)
+        }
+
+        /// Why a call on this handle failed, if it was closed under it.
+        fn closed_error(&self) -> Option<HttpError> {
+            if !self.is_closed() {
+                None
+            } else if self.expired.load(Ordering::SeqCst) {
+                Some(HttpError::from_code(ERROR_INTERNET_TIMEOUT))
+            } else {
+                Some(HttpError::Cancelled)
+            }
+        }
+
+        /// Closes the handle at `deadline` unless it has been dropped first.
+        fn watch(handle: &Arc<Handle>, deadline: Instant) {
+            let handle = Arc::downgrade(handle);
+            std::thread::spawn(move || loop {
+                if handle.strong_count() == 0 {
+                    return;
+                }
+                let now = Instant::now();
+                if now >= deadline {
+                    if let Some(handle) = handle.upgrade() {
+                        handle.expired.store(true, Ordering::SeqCst);
+                        handle.close();
+                    }
+                    return;
+                }
+                // Poll so the thread goes away soon after the response does.
+                std::thread::sleep((deadline - now).min(Duration::from_millis(100)));
+            });
+        }
+    }
+
+    // SAFETY: an HINTERNET is an opaque token into WinINet's process-wide
+    // handle table rather than memory owned by this thread, and WinINet
+    // serialises the calls made on it, so a handle may be used and closed
+    // from any thread. Reading one response from two threads at once is safe
+    // but interleaves the data.
+    unsafe impl Send for Handle {}
+    unsafe impl Sync for Handle {}
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            self.close();
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct Internet {
+        handle: Arc<Handle>,
+        settings: Settings,
+    }
+
+    /// Per-handle defaults that connections and requests inherit.
+    #[derive(Debug, Clone)]
+    struct Settings {
+        cache_policy: CachePolicy,
+        logger: Option<Arc<Logger>>,
+    }
+
+    /// What a `Logger` is told about. Bodies only appear when the logger was
+    /// built `with_bodies(true)`, so secrets in them aren't logged by default.
+    #[derive(Debug)]
+    pub enum LogEvent<'a> {
+        /// A request is about to be sent. `headers` are the extra request
+        /// headers; WinINet's own (Host, User-Agent ...) aren't included.
+        /// Credential and cookie values read `<redacted>` unless the logger
+        /// was built `with_secrets(true)`.
+        Request {
+            method: &'a str,
+            url: &'a str,
+            headers: &'a str,
+            body: Option<&'a [u8]>,
+        },
+        /// The response headers arrived.
+        Response {
+            url: &'a str,
+            status: u16,
+            content_length: Option<u64>,
+        },
+        /// A piece of the response body was read.
+        Body { url: &'a str, data: &'a [u8] },
+        /// The response body was read to the end.
+        Complete {
+            url: &'a str,
+            status: u16,
+            bytes: u64,
+        },
+    }
+
+    /// A callback receiving a `LogEvent` for every request made through a
+    /// handle, installed with `Internet::set_logger`.
+    pub struct Logger {
+        callback: Box<dyn Fn(&LogEvent) + Send + Sync>,
+        bodies: bool,
+        secrets: bool,
+    }
+
+    impl Logger {
+        pub fn new(callback: impl Fn(&LogEvent) + Send + Sync + 'static) -> Logger {
+            Logger {
+                callback: Box::new(callback),
+                bodies: false,
+                secrets: false,
+            }
+        }
+
+        /// Also report request and response bodies.
+        pub fn with_bodies(mut self, bodies: bool) -> Logger {
+            self.bodies = bodies;
+            self
+        }
+
+        /// Also report the values of `Authorization`, `Proxy-Authorization`
+        /// and `Cookie` request headers, which are otherwise logged as
+        /// `<redacted>`.
+        pub fn with_secrets(mut self, secrets: bool) -> Logger {
+            self.secrets = secrets;
+            self
+        }
+
+        fn log(&self, event: LogEvent) {
+            (self.callback)(&event)
+        }
+
+        // The request headers as they are logged.
+        fn headers<'h>(&self, headers: &'h str) -> Cow<'h, str> {
+            let secret = |line: &str| {
+                line.split(':').next().is_some_and(|name| {
+                    ["Authorization", "Proxy-Authorization", "Cookie"]
+                        .iter()
+                        .any(|secret| name.trim().eq_ignore_ascii_case(secret))
+                })
+            };
+            if self.secrets || !headers.split_terminator("\r\n").any(secret) {
+                return Cow::Borrowed(headers);
+            }
+            Cow::Owned(
+                headers
+                    .split_terminator("\r\n")
+                    .map(|line| match line.split_once(':') {
+                        Some((name, _)) if secret(line) => format!("{}: <redacted>\r\n", name),
+                        _ => format!("{}\r\n", line),
+                    })
+                    .collect(),
+            )
+        }
+    }
+
+    impl fmt::Debug for Logger {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_struct("Logger")
+                .field("bodies", &self.bodies)
+                .field("secrets", &self.secrets)
+                .finish()
+        }
+    }
+
+    /// Logging state carried by a response whose handle has a logger.
+    struct ResponseLog {
+        logger: Arc<Logger>,
+        url: String,
+        status: u16,
+        bytes: AtomicU64,
+        done: AtomicBool,
+    }
+
+    impl ResponseLog {
+        fn read(&self, data: &[u8]) {
+            if data.is_empty() {
+                if !self.done.swap(true, Ordering::SeqCst) {
+                    self.logger.log(LogEvent::Complete {
+                        url: &self.url,
+                        status: self.status,
+                        bytes: self.bytes.load(Ordering::SeqCst),
+                    });
+                }
+                return;
+            }
+            self.bytes.fetch_add(data.len() as u64, Ordering::SeqCst);
+            if self.logger.bodies {
+                self.logger.log(LogEvent::Body {
+                    url: &self.url,
+                    data,
+                });
+            }
+        }
+    }
+
+    /// Protocol versions for `Internet::set_min_tls_version`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum TlsVersion {
+        Tls1_0,
+        Tls1_1,
+        Tls1_2,
+        Tls1_3,
+    }
+
+    impl TlsVersion {
+        // The SP_PROT_*_CLIENT bits for this version and every later one.
+        fn protocols_from(self) -> DWORD {
+            const TLS1_0: DWORD = 0x0080;
+            const TLS1_1: DWORD = 0x0200;
+            const TLS1_2: DWORD = 0x0800;
+            const TLS1_3: DWORD = 0x2000;
+            match self {
+                TlsVersion::Tls1_0 => TLS1_0 | TLS1_1 | TLS1_2 | TLS1_3,
+                TlsVersion::Tls1_1 => TLS1_1 | TLS1_2 | TLS1_3,
+                TlsVersion::Tls1_2 => TLS1_2 | TLS1_3,
+                TlsVersion::Tls1_3 => TLS1_3,
+            }
+        }
+    }
+
+    // Not in winapi.
+    const INTERNET_OPTION_SECURE_PROTOCOLS: DWORD = 151;
+    const INTERNET_OPTION_ENABLE_HTTP_PROTOCOL: DWORD = 148;
+    const INTERNET_OPTION_HTTP_PROTOCOL_USED: DWORD = 149;
+    const HTTP_PROTOCOL_FLAG_HTTP2: DWORD = 0x2;
+
+    /// The protocol a response came over, from `Response::http_version`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum HttpVersion {
+        Http1_0,
+        Http1_1,
+        Http2,
+    }
+
+    /// How requests use the WinINet cache.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum CachePolicy {
+        /// WinINet's own behaviour: fresh cached copies are served, stale
+        /// ones revalidated, and responses stored (no extra flags).
+        Default,
+        /// Always go to the server and store nothing
+        /// (`INTERNET_FLAG_RELOAD | INTERNET_FLAG_DONT_CACHE`). This is what
+        /// handles use unless told otherwise.
+        NoCache,
+        /// Always go to the server but keep the response in the cache
+        /// (`INTERNET_FLAG_RELOAD`).
+        ReloadFromServer,
+        /// Answer from the cache only and fail if nothing is cached
+        /// (`INTERNET_FLAG_FROM_CACHE`).
+        CacheOnly,
+    }
+
+    impl CachePolicy {
+        fn flags(self) -> DWORD {
+            match self {
+                CachePolicy::Default => 0,
+                CachePolicy::NoCache => INTERNET_FLAG_RELOAD | INTERNET_FLAG_DONT_CACHE,
+                CachePolicy::ReloadFromServer => INTERNET_FLAG_RELOAD,
+                CachePolicy::CacheOnly => INTERNET_FLAG_FROM_CACHE,
+            }
+        }
+    }
+
+    enum OpenType {
+        Proxy = winapi::um::wininet::INTERNET_OPEN_TYPE_PROXY as isize,
+        Direct = winapi::um::wininet::INTERNET_OPEN_TYPE_DIRECT as isize,
+        Preconfig = winapi::um::wininet::INTERNET_OPEN_TYPE_PRECONFIG as isize,
+        PreconfigWithNoAutoproxy =
+            winapi::um::wininet::INTERNET_OPEN_TYPE_PRECONFIG_WITH_NO_AUTOPROXY as isize,
+    }
+
+    #[allow(dead_code)]
+    enum InternetFlag {
+        None = 0,
+        Offline = winapi::um::wininet::INTERNET_FLAG_OFFLINE as isize,
+        Async = winapi::um::wininet::INTERNET_FLAG_ASYNC as isize,
+        OfflineAndAsync = winapi::um::wininet::INTERNET_FLAG_OFFLINE as isize
+            | winapi::um::wininet::INTERNET_FLAG_ASYNC as isize,
+    }
+
+    impl From<OpenType> for DWORD {
+        fn from(w: OpenType) -> Self {
+            w as DWORD
+        }
+    }
+
+    impl From<InternetFlag> for DWORD {
+        fn from(w: InternetFlag) -> Self {
+            w as DWORD
+        }
+    }
+
+    /// A handle's proxy configuration, from `Internet::proxy_info`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ProxyInfo {
+        /// Whether requests go straight to the server.
+        pub direct: bool,
+        /// The proxy list as WinINet holds it, e.g. `"proxy:8080"` or
+        /// `"http=proxy:80;https=proxy:443"`.
+        pub proxy: Option<String>,
+        /// Hosts that skip the proxy, e.g. `"<local>;*.example.com"`.
+        pub bypass: Option<String>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Scheme {
+        Http,
+        Https,
+    }
+
+    impl Scheme {
+        pub fn default_port(self) -> u16 {
+            match self {
+                Scheme::Http => INTERNET_DEFAULT_HTTP_PORT as u16,
+                Scheme::Https => INTERNET_DEFAULT_HTTPS_PORT as u16,
+            }
+        }
+    }
+
+    /// A connection to one host that any number of requests can share, so
+    /// WinINet can keep the socket (and TLS session) alive between them.
+    #[derive(Debug, Clone)]
+    pub struct Connection {
+        handle: Arc<Handle>,
+        secure: bool,
+        host: String,
+        port: INTERNET_PORT,
+        settings: Settings,
+        // The last Digest challenge answered on this connection, so later
+        // requests reuse its nonce with a rising count.
+        digest: Arc<Mutex<Option<DigestNonce>>>,
+    }
+
+    #[derive(Debug)]
+    struct DigestNonce {
+        challenge: Challenge,
+        cnonce: String,
+        count: u32,
+    }
+
+    impl Connection {
+        fn open(
+            session: &Arc<Handle>,
+            host: &str,
+            port: INTERNET_PORT,
+            secure: bool,
+            settings: Settings,
+        ) -> Result<Connection, HttpError> {
+            let wide_host = wide(host, "host")?;
+            let handle = unsafe {
+                InternetConnectW(
+                    session.raw,
+                    wide_host.as_ptr(),
+                    port,
+                    null(),
+                    null(),
+                    INTERNET_SERVICE_HTTP,
+                    0,
+                    0,
+                )
+            };
+            Ok(Connection {
+                handle: Arc::new(Handle::new(handle, Some(session.clone()))?),
+                secure,
+                host: host.to_string(),
+                port,
+                settings,
+                digest: Arc::new(Mutex::new(None)),
+            })
+        }
+
+        fn url(&self, path: &str) -> String {
+            let (scheme, default_port) = if self.secure {
+                ("https", INTERNET_DEFAULT_HTTPS_PORT)
+            } else {
+                ("http", INTERNET_DEFAULT_HTTP_PORT)
+            };
+            if u32::from(self.port) == default_port {
+                format!("{}://{}{}", scheme, self.host, path)
+            } else {
+                format!("{}://{}:{}{}", scheme, self.host, self.port, path)
+            }
+        }
+
+        pub fn request(
+            &self,
+            method: &str,
+            path: &str,
+            headers: Option<&str>,
+            body: Option<&[u8]>,
+        ) -> Result<Response, HttpError> {
+            let mut builder = self.request_builder(method, path);
+            builder.headers = headers.unwrap_or("").to_string();
+            builder.body = body.map(|body| Body::Bytes(Cow::Borrowed(body)));
+            builder.send()
+        }
+
+        pub fn request_builder<'a>(&self, method: &str, path: &str) -> RequestBuilder<'a> {
+            RequestBuilder::new(method, Ok((self.clone(), path.to_string())))
+        }
+    }
+
+    /// Connections shared across requests, one per scheme, host and port, so
+    /// repeated requests to a host skip the TCP and TLS handshakes. A
+    /// connection unused for longer than the pool's TTL is closed the next
+    /// time the pool is used. Safe to share between threads.
+    #[derive(Debug)]
+    pub struct Pool {
+        internet: Internet,
+        ttl: Duration,
+        connections: Mutex<HashMap<PoolKey, (Connection, Instant)>>,
+    }
+
+    // Whether TLS is used, the lowercased host, and the port.
+    type PoolKey = (bool, String, INTERNET_PORT);
+
+    impl Pool {
+        pub fn get<'a>(&self, url: &str) -> RequestBuilder<'a> {
+            self.request_builder("GET", url)
+        }
+
+        pub fn request_builder<'a>(&self, method: &str, url: &str) -> RequestBuilder<'a> {
+            let target = Url::parse(url).and_then(|url| {
+                let connection = self.connection(url.secure, &url.host, url.port)?;
+                Ok((connection, url.path))
+            });
+            RequestBuilder::new(method, target)
+        }
+
+        /// How many connections are currently pooled.
+        pub fn len(&self) -> usize {
+            self.connections.lock().unwrap().len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+
+        fn connection(
+            &self,
+            secure: bool,
+            host: &str,
+            port: INTERNET_PORT,
+        ) -> Result<Connection, HttpError> {
+            let now = Instant::now();
+            let mut connections = self.connections.lock().unwrap();
+            connections.retain(|_, (_, last_used)| now.duration_since(*last_used) <= self.ttl);
+            let key = (secure, host.to_ascii_lowercase(), port);
+            if let Some((connection, last_used)) = connections.get_mut(&key) {
+                *last_used = now;
+                return Ok(connection.clone());
+            }
+            let connection = self.internet.open_connection(host, port, secure)?;
+            connections.insert(key, (connection.clone(), now));
+            Ok(connection)
+        }
+    }
+
+    /// A request handle and its response. It holds a reference to the
+    /// connection and `Internet` handles it came from, so those stay open
+    /// until the response is dropped even if the caller drops them first;
+    /// there is no lifetime to thread through and no way to outlive a
+    /// parent.
+    pub struct Response {
+        handle: Arc<Handle>,
+        // Whether WinINet already undid the Content-Encoding.
+        decoded: bool,
+        // Whether INTERNET_FLAG_RAW_DATA was requested, which leaves any
+        // chunked framing in the body.
+        raw: bool,
+        // Set up on the first read, once the headers are in.
+        reader: OnceLock<Mutex<chunked::Reader<RawBody>>>,
+        log: Option<ResponseLog>,
+    }
+
+    // The body exactly as InternetReadFile returns it, for the reader.
+    struct RawBody(Arc<Handle>);
+
+    impl io::Read for RawBody {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            read_raw(&self.0, buf)
+        }
+    }
+
+    struct Url {
+        secure: bool,
+        host: String,
+        port: INTERNET_PORT,
+        path: String,
+    }
+
+    impl Url {
+        fn parse(url: &str) -> Result<Url, HttpError> {
+            let url = wide(url, "URL")?;
+            let mut components: URL_COMPONENTSW = unsafe { std::mem::zeroed() };
+            components.dwStructSize = std::mem::size_of::<URL_COMPONENTSW>() as DWORD;
+            // Non-zero lengths with null pointers ask InternetCrackUrlW to
+            // point into the input string instead of copying.
+            components.dwHostNameLength = 1;
+            components.dwUrlPathLength = 1;
+            components.dwExtraInfoLength = 1;
+
+            let result = unsafe { InternetCrackUrlW(url.as_ptr(), 0, 0, &mut components) };
+            if result == 0 {
+                return Err(HttpError::last_error());
+            }
+
+            let part = |ptr: LPWSTR, len: DWORD| {
+                if ptr.is_null() {
+                    String::new()
+                } else {
+                    String::from_utf16_lossy(unsafe {
+                        std::slice::from_raw_parts(ptr, len as usize)
+                    })
+                }
+            };
+
+            let secure = match components.nScheme {
+                INTERNET_SCHEME_HTTP => false,
+                INTERNET_SCHEME_HTTPS => true,
+                _ => return Err(HttpError::from_code(ERROR_INTERNET_UNRECOGNIZED_SCHEME)),
+            };
+            let mut path = part(components.lpszUrlPath, components.dwUrlPathLength);
+            if path.is_empty() {
+                path.push('/');
+            }
+            let extra = part(components.lpszExtraInfo, components.dwExtraInfoLength);
+            path.push_str(extra.split('#').next().unwrap_or(""));
+
+            Ok(Url {
+                secure,
+                host: part(components.lpszHostName, components.dwHostNameLength),
+                port: components.nPort,
+                path,
+            })
+        }
+    }
+
+    /// Takes the parts of a URL parsed by the `url` crate, leaving out the
+    /// fragment.
+    #[cfg(feature = "url")]
+    impl TryFrom<&url::Url> for Url {
+        type Error = HttpError;
+
+        fn try_from(url: &url::Url) -> Result<Url, HttpError> {
+            let secure = match url.scheme() {
+                "http" => false,
+                "https" => true,
+                _ => return Err(HttpError::from_code(ERROR_INTERNET_UNRECOGNIZED_SCHEME)),
+            };
+            let host = match url.host() {
+                // InternetConnectW wants IPv6 addresses without brackets.
+                Some(url::Host::Ipv6(address)) => address.to_string(),
+                Some(host) => host.to_string(),
+                None => return Err(HttpError::InvalidInput(format!("URL {} has no host", url))),
+            };
+            let mut path = url.path().to_string();
+            if let Some(query) = url.query() {
+                path.push('?');
+                path.push_str(query);
+            }
+            Ok(Url {
+                secure,
+                host,
+                port: url.port_or_known_default().unwrap_or_default(),
+                path,
+            })
+        }
+    }
+
+    impl Internet {
+        pub fn open(agent: &str, proxy: Option<&str>) -> Result<Internet, HttpError> {
+            match proxy {
+                Some(proxy) => Internet::open_with_proxy(agent, proxy, None),
+                None => {
+                    Internet::open_with(agent, OpenType::Direct, None, None, InternetFlag::None)
+                }
+            }
+        }
+
+        /// Opens a handle that follows the system (Internet Options) proxy
+        /// settings, including WPAD and PAC auto-discovery.
+        pub fn open_with_system_proxy(agent: &str) -> Result<Internet, HttpError> {
+            Internet::open_with(agent, OpenType::Preconfig, None, None, InternetFlag::None)
+        }
+
+        /// Like `open_with_system_proxy`, but skips proxy auto-discovery and
+        /// uses only the statically configured proxy.
+        pub fn open_with_system_proxy_no_autoproxy(agent: &str) -> Result<Internet, HttpError> {
+            Internet::open_with(
+                agent,
+                OpenType::PreconfigWithNoAutoproxy,
+                None,
+                None,
+                InternetFlag::None,
+            )
+        }
+
+        /// Opens a handle that never touches the network: every request is
+        /// answered from the WinINet cache, or fails if nothing is cached.
+        pub fn open_offline(agent: &str) -> Result<Internet, HttpError> {
+            Internet::open_with(agent, OpenType::Direct, None, None, InternetFlag::Offline)
+        }
+
+        /// Opens a handle that sends everything through `proxy` except hosts
+        /// matched by `bypass`, e.g. `"localhost;*.internal.example.com;<local>"`.
+        pub fn open_with_proxy(
+            agent: &str,
+            proxy: &str,
+            bypass: Option<&str>,
+        ) -> Result<Internet, HttpError> {
+            Internet::open_with(
+                agent,
+                OpenType::Proxy,
+                Some(proxy),
+                bypass,
+                InternetFlag::None,
+            )
+        }
+
+        fn open_with(
+            agent: &str,
+            open_type: OpenType,
+            proxy: Option<&str>,
+            bypass: Option<&str>,
+            flags: InternetFlag,
+        ) -> Result<Internet, HttpError> {
+            let agent = wide(agent, "agent")?;
+            let proxy = proxy.map(|proxy| wide(proxy, "proxy")).transpose()?;
+            let bypass = bypass
+                .map(|bypass| wide(bypass, "bypass list"))
+                .transpose()?;
+            // Offline handles must be allowed to read the cache.
+            let cache_policy = match flags {
+                InternetFlag::Offline | InternetFlag::OfflineAndAsync => CachePolicy::Default,
+                InternetFlag::None | InternetFlag::Async => CachePolicy::NoCache,
+            };
+
+            let internet_handle = unsafe {
+                InternetOpenW(
+                    agent.as_ptr(),
+                    open_type.into(),
+                    proxy.as_ref().map_or(null(), |proxy| proxy.as_ptr()),
+                    bypass.as_ref().map_or(null(), |bypass| bypass.as_ptr()),
+                    flags.into(),
+                )
+            };
+
+            Ok(Internet {
+                handle: Arc::new(Handle::new(internet_handle, None)?),
+                settings: Settings {
+                    cache_policy,
+                    logger: None,
+                },
+            })
+        }
+
+        /// Takes ownership of a handle from `InternetOpenW`; it is closed
+        /// when the returned `Internet` and everything opened from it are
+        /// dropped. The cache policy starts as `CachePolicy::Default`.
+        ///
+        /// # Safety
+        ///
+        /// `raw` must be a valid session handle that nothing else closes.
+        pub unsafe fn from_raw_handle(raw: HINTERNET) -> Result<Internet, HttpError> {
+            Ok(Internet {
+                handle: Arc::new(Handle::new(raw, None)?),
+                settings: Settings {
+                    cache_policy: CachePolicy::Default,
+                    logger: None,
+                },
+            })
+        }
+
+        /// The session handle, for WinINet calls this crate doesn't wrap. It
+        /// is still owned by `self`: don't close it, and don't use it after
+        /// `self` and every connection and response opened from it are gone.
+        pub fn as_raw_handle(&self) -> HINTERNET {
+            self.handle.raw
+        }
+
+        /// Gives up ownership of the session handle, which the caller must
+        /// then close with `InternetCloseHandle`. Fails, handing `self` back,
+        /// while connections, responses or pools opened from it still hold
+        /// the handle.
+        pub fn into_raw_handle(self) -> Result<HINTERNET, Internet> {
+            match Arc::try_unwrap(self.handle) {
+                Ok(handle) => {
+                    // Marked closed so dropping it leaves the handle open.
+                    handle.closed.store(true, Ordering::SeqCst);
+                    Ok(handle.raw)
+                }
+                Err(handle) => Err(Internet {
+                    handle,
+                    settings: self.settings,
+                }),
+            }
+        }
+
+        pub fn get(&self, url: &str, headers: Option<&str>) -> Result<Response, HttpError> {
+            self.get_with_flags(url, headers, 0)
+        }
+
+        /// Like `get`, but a 3xx response is returned as is instead of being
+        /// followed, so its `Location` header can be inspected.
+        pub fn get_no_redirect(
+            &self,
+            url: &str,
+            headers: Option<&str>,
+        ) -> Result<Response, HttpError> {
+            self.get_with_flags(url, headers, INTERNET_FLAG_NO_AUTO_REDIRECT)
+        }
+
+        /// Like `get`, with extra `INTERNET_FLAG_*` values ORed into the
+        /// request flags. `INTERNET_FLAG_SECURE` is already set for `https`
+        /// URLs; passing `INTERNET_FLAG_RAW_DATA | INTERNET_FLAG_SECURE`
+        /// restores the flags `get` used to force on every request.
+        pub fn get_with_flags(
+            &self,
+            url: &str,
+            headers: Option<&str>,
+            flags: DWORD,
+        ) -> Result<Response, HttpError> {
+            let secure = is_https(url);
+            if let Some(headers) = headers {
+                check_headers(headers)?;
+            }
+            if let Some(logger) = &self.settings.logger {
+                logger.log(LogEvent::Request {
+                    method: "GET",
+                    url,
+                    headers: &logger.headers(headers.unwrap_or("")),
+                    body: None,
+                });
+            }
+            let url_text = url;
+            let headers = headers
+                .map(|headers| wide(headers, "headers"))
+                .transpose()?;
+            let url = wide(url, "URL")?;
+            let handle = unsafe {
+                InternetOpenUrlW(
+                    self.handle.raw,
+                    url.as_ptr(),
+                    headers.as_ref().map_or(null(), |headers| headers.as_ptr()),
+                    0xFFFFFFFF,
+                    self.settings.cache_policy.flags()
+                        | if secure { INTERNET_FLAG_SECURE } else { 0 }
+                        | flags,
+                    0,
+                )
+            };
+            Ok(Response {
+                handle: Arc::new(Handle::new(handle, Some(self.handle.clone()))?),
+                decoded: false,
+                raw: flags & INTERNET_FLAG_RAW_DATA != 0,
+                reader: OnceLock::new(),
+                log: None,
+            }
+            .logged(self.settings.logger.as_ref(), url_text))
+        }
+
+        pub fn post(
+            &self,
+            url: &str,
+            headers: Option<&str>,
+            body: &[u8],
+        ) -> Result<Response, HttpError> {
+            self.request("POST", url, headers, Some(body))
+        }
+
+        /// Sends a `HEAD` request. The response carries the status and
+        /// headers only; reading its body yields end-of-stream at once.
+        pub fn head(&self, url: &str, headers: Option<&str>) -> Result<Response, HttpError> {
+            self.request("HEAD", url, headers, None)
+        }
+
+        /// Sends a request with an arbitrary verb. `method` is passed to
+        /// WinINet verbatim, so extension methods such as `"REPORT"` work too.
+        /// A `None` body sends no entity.
+        pub fn request(
+            &self,
+            method: &str,
+            url: &str,
+            headers: Option<&str>,
+            body: Option<&[u8]>,
+        ) -> Result<Response, HttpError> {
+            let mut builder = self.request_builder(method, url);
+            builder.headers = headers.unwrap_or("").to_string();
+            builder.body = body.map(|body| Body::Bytes(Cow::Borrowed(body)));
+            builder.send()
+        }
+
+        pub fn request_builder<'a>(&self, method: &str, url: &str) -> RequestBuilder<'a> {
+            self.builder_for(method, Url::parse(url))
+        }
+
+        /// Like `request_builder`, for a URL already parsed by the `url`
+        /// crate. Its fragment is not sent.
+        #[cfg(feature = "url")]
+        pub fn url_request_builder<'a>(&self, method: &str, url: &url::Url) -> RequestBuilder<'a> {
+            self.builder_for(method, Url::try_from(url))
+        }
+
+        /// Like `get`, for a URL already parsed by the `url` crate. The
+        /// request goes over `url_request_builder`.
+        #[cfg(feature = "url")]
+        pub fn get_url(
+            &self,
+            url: &url::Url,
+            headers: Option<&str>,
+        ) -> Result<Response, HttpError> {
+            let mut builder = self.url_request_builder("GET", url);
+            builder.headers = headers.unwrap_or("").to_string();
+            builder.send()
+        }
+
+        fn builder_for<'a>(&self, method: &str, url: Result<Url, HttpError>) -> RequestBuilder<'a> {
+            let target = url.and_then(|url| {
+                let connection = self.open_connection(&url.host, url.port, url.secure)?;
+                Ok((connection, url.path))
+            });
+            RequestBuilder::new(method, target)
+        }
+
+        /// Opens a connection to `host` that requests can reuse. Port 443 is
+        /// spoken to over TLS, anything else in the clear.
+        pub fn connect(&self, host: &str, port: u16) -> Result<Connection, HttpError> {
+            self.open_connection(host, port, u32::from(port) == INTERNET_DEFAULT_HTTPS_PORT)
+        }
+
+        /// Opens a connection with an explicit scheme, on its default port
+        /// unless `port` is given, e.g. `(Scheme::Https, "example.com", Some(8443))`.
+        pub fn connect_with(
+            &self,
+            scheme: Scheme,
+            host: &str,
+            port: Option<u16>,
+        ) -> Result<Connection, HttpError> {
+            let port = port.unwrap_or_else(|| scheme.default_port());
+            self.open_connection(host, port, scheme == Scheme::Https)
+        }
+
+        /// Opens a connection to the scheme, host and port of `url`; any
+        /// path in it is ignored.
+        pub fn connect_url(&self, url: &str) -> Result<Connection, HttpError> {
+            let url = Url::parse(url)?;
+            self.open_connection(&url.host, url.port, url.secure)
+        }
+
+        fn open_connection(
+            &self,
+            host: &str,
+            port: INTERNET_PORT,
+            secure: bool,
+        ) -> Result<Connection, HttpError> {
+            Connection::open(&self.handle, host, port, secure, self.settings.clone())
+        }
+
+        /// Downloads `url` into `path`, asking only for the bytes past what
+        /// `path` already holds. If the server ignores the range and sends the
+        /// whole body, the file is rewritten from scratch. Returns the final
+        /// file size.
+        pub fn resume_download<P: AsRef<Path>>(
+            &self,
+            url: &str,
+            path: P,
+        ) -> Result<u64, HttpError> {
+            let path = path.as_ref();
+            let existing = fs::metadata(path).map_or(0, |metadata| metadata.len());
+            let mut builder = self.request_builder("GET", url);
+            if existing > 0 {
+                builder = builder.range(existing, None);
+            }
+            let response = builder.send()?;
+            match response.status_code()? {
+                206 => {
+                    if response.content_range().map(|range| range.start) != Some(existing) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "server resumed from a different offset",
+                        )
+                        .into());
+                    }
+                    let mut file = OpenOptions::new().append(true).open(path)?;
+                    Ok(existing + response.write_body(&mut file, |_| {})?)
+                }
+                // Nothing is left to fetch.
+                416 if existing > 0 => Ok(existing),
+                200..=299 => {
+                    let mut file = File::create(path)?;
+                    Ok(response.write_body(&mut file, |_| {})?)
+                }
+                status_code => Err(io::Error::other(format!(
+                    "server responded with status {}",
+                    status_code
+                ))
+                .into()),
+            }
+        }
+
+        pub fn set_connect_timeout(&self, timeout: Duration) -> Result<(), HttpError> {
+            self.set_timeout(INTERNET_OPTION_CONNECT_TIMEOUT, timeout)
+        }
+
+        pub fn set_receive_timeout(&self, timeout: Duration) -> Result<(), HttpError> {
+            self.set_timeout(INTERNET_OPTION_RECEIVE_TIMEOUT, timeout)
+        }
+
+        pub fn set_send_timeout(&self, timeout: Duration) -> Result<(), HttpError> {
+            self.set_timeout(INTERNET_OPTION_SEND_TIMEOUT, timeout)
+        }
+
+        /// The cache policy for requests made from now on through this
+        /// handle, including connections opened after the call.
+        pub fn set_cache_policy(&mut self, policy: CachePolicy) {
+            self.settings.cache_policy = policy;
+        }
+
+        /// Reports every request made from now on through this handle, and
+        /// its response, to `logger`.
+        pub fn set_logger(&mut self, logger: Logger) {
+            self.settings.logger = Some(Arc::new(logger));
+        }
+
+        /// A connection pool drawing on this handle. Connections idle for
+        /// longer than `ttl` are closed.
+        pub fn pool(&self, ttl: Duration) -> Pool {
+            Pool {
+                internet: Internet {
+                    handle: self.handle.clone(),
+                    settings: self.settings.clone(),
+                },
+                ttl,
+                connections: Mutex::new(HashMap::new()),
+            }
+        }
+
+        /// Refuses TLS handshakes that would settle on anything older than
+        /// `version`, SSL 3.0 included. Applies to connections opened after
+        /// the call.
+        pub fn set_min_tls_version(&self, version: TlsVersion) -> Result<(), HttpError> {
+            set_dword_option(
+                self.handle.raw,
+                INTERNET_OPTION_SECURE_PROTOCOLS,
+                version.protocols_from(),
+            )
+        }
+
+        /// Credentials for a proxy that requires authentication, such as a
+        /// corporate Basic or NTLM proxy given to `open`.
+        pub fn set_proxy_credentials(&self, user: &str, pass: &str) -> Result<(), HttpError> {
+            set_string_option(self.handle.raw, INTERNET_OPTION_PROXY_USERNAME, user)?;
+            set_string_option(self.handle.raw, INTERNET_OPTION_PROXY_PASSWORD, pass)
+        }
+
+        /// Passes `value` to `InternetSetOptionW` for an `INTERNET_OPTION_*`
+        /// this crate doesn't wrap. Connections opened afterwards inherit it.
+        ///
+        /// # Safety
+        ///
+        /// `value` must be laid out as `option` expects, including any
+        /// pointers inside it being valid for as long as WinINet uses them.
+        pub unsafe fn set_option(&self, option: DWORD, value: &[u8]) -> Result<(), HttpError> {
+            set_option(self.handle.raw, option, value)
+        }
+
+        /// `set_option` for the many options that take a single `DWORD`.
+        pub fn set_option_dword(&self, option: DWORD, value: DWORD) -> Result<(), HttpError> {
+            set_dword_option(self.handle.raw, option, value)
+        }
+
+        /// Lets https connections opened after the call negotiate HTTP/2
+        /// where the server offers it. Returns `false` on Windows versions
+        /// whose WinINet lacks HTTP/2, which keep using HTTP/1.1.
+        pub fn enable_http2(&self) -> bool {
+            set_dword_option(
+                self.handle.raw,
+                INTERNET_OPTION_ENABLE_HTTP_PROTOCOL,
+                HTTP_PROTOCOL_FLAG_HTTP2,
+            )
+            .is_ok()
+        }
+
+        /// The proxy settings WinINet resolved for this handle, e.g. what
+        /// `open_with_system_proxy` picked up from the system. A PAC script
+        /// chooses proxies per URL, so its choices aren't reflected here.
+        pub fn proxy_info(&self) -> Result<ProxyInfo, HttpError> {
+            // u64s keep the structure's pointer fields aligned.
+            let mut buffer = vec![0u64; 64];
+            loop {
+                let mut len = (buffer.len() * 8) as DWORD;
+                // The W variant hands back ANSI strings for this option, so the
+                // A variant is used to make that explicit.
+                let result = unsafe {
+                    InternetQueryOptionA(
+                        self.handle.raw,
+                        INTERNET_OPTION_PROXY,
+                        buffer.as_mut_ptr() as *mut winapi::ctypes::c_void,
+                        &mut len,
+                    )
+                };
+                if result != 0 {
+                    break;
+                }
+                if unsafe { GetLastError() } != ERROR_INSUFFICIENT_BUFFER {
+                    return Err(HttpError::last_error());
+                }
+                buffer.resize((len as usize).div_ceil(8).max(buffer.len() * 2), 0);
+            }
+            let info = unsafe { &*(buffer.as_ptr() as *const INTERNET_PROXY_INFO) };
+            let string = |ptr: LPCWSTR| {
+                if ptr.is_null() {
+                    return None;
+                }
+                let text = unsafe { std::ffi::CStr::from_ptr(ptr as *const std::ffi::c_char) };
+                Some(text.to_string_lossy().into_owned()).filter(|text| !text.is_empty())
+            };
+            Ok(ProxyInfo {
+                direct: info.dwAccessType == INTERNET_OPEN_TYPE_DIRECT,
+                proxy: string(info.lpszProxy),
+                bypass: string(info.lpszProxyBypass),
+            })
+        }
+
+        /// The raw value of an `INTERNET_OPTION_*` on this handle, from
+        /// `InternetQueryOptionW`. The bytes carry no alignment, so copy
+        /// structures out with `ptr::read_unaligned`. Strings referred to by
+        /// such structures, as in `INTERNET_PROXY_INFO`, are written into the
+        /// same buffer, so their pointers stay valid while it is untouched.
+        pub fn query_option(&self, option: DWORD) -> Result<Vec<u8>, HttpError> {
+            query_option(self.handle.raw, option)
+        }
+
+        /// `query_option` for the options that hold a single `DWORD`, such as
+        /// the timeouts.
+        pub fn query_option_dword(&self, option: DWORD) -> Result<DWORD, HttpError> {
+            query_dword_option(self.handle.raw, option).ok_or_else(HttpError::last_error)
+        }
+
+        fn set_timeout(&self, option: DWORD, timeout: Duration) -> Result<(), HttpError> {
+            set_dword_option(self.handle.raw, option, duration_millis(timeout))
+        }
+    }
+
+    enum Body<'a> {
+        Bytes(Cow<'a, [u8]>),
+        Reader(Box<dyn io::Read + 'a>, Option<u64>),
+    }
+
+    pub struct RequestBuilder<'a> {
+        connection: Option<Connection>,
+        method: String,
+        path: String,
+        headers: String,
+        body: Option<Body<'a>>,
+        timeout: Option<Duration>,
+        flags: DWORD,
+        security_flags: DWORD,
+        retry: Option<RetryPolicy>,
+        // Headers that must replace what WinINet would send itself.
+        replace_headers: Vec<(&'static str, String)>,
+        deadline: Option<Instant>,
+        cache_policy: Option<CachePolicy>,
+        auto_decompress: bool,
+        client_cert: Option<ClientCert>,
+        max_redirects: Option<u32>,
+        digest: Option<(String, String)>,
+        error: Option<HttpError>,
+    }
+
+    impl<'a> RequestBuilder<'a> {
+        fn new(
+            method: &str,
+            target: Result<(Connection, String), HttpError>,
+        ) -> RequestBuilder<'a> {
+            let (connection, path, error) = match target {
+                Ok((connection, path)) => (Some(connection), path, None),
+                Err(error) => (None, String::new(), Some(error)),
+            };
+            RequestBuilder {
+                connection,
+                method: method.to_string(),
+                path,
+                headers: String::new(),
+                body: None,
+                timeout: None,
+                flags: 0,
+                security_flags: 0,
+                retry: None,
+                replace_headers: Vec::new(),
+                deadline: None,
+                cache_policy: None,
+                auto_decompress: false,
+                client_cert: None,
+                max_redirects: None,
+                digest: None,
+                error,
+            }
+        }
+
+        /// Sets a request header, replacing any earlier value for `name`.
+        /// A name or value that could smuggle in another header line makes
+        /// `send` fail with `HttpError::InvalidInput`.
+        pub fn header(mut self, name: &str, value: &str) -> Self {
+            match check_header(name, value) {
+                Ok(()) => self.set_header(name, value),
+                Err(error) => {
+                    self.error.get_or_insert(error);
+                }
+            }
+            self
+        }
+
+        /// Adds another `name` line after any already set, for headers that
+        /// may repeat, such as `Accept` or `Cookie`. Validated like `header`.
+        pub fn header_append(mut self, name: &str, value: &str) -> Self {
+            match check_header(name, value) {
+                Ok(()) => self.push_header(name, value),
+                Err(error) => {
+                    self.error.get_or_insert(error);
+                }
+            }
+            self
+        }
+
+        pub fn body(mut self, body: &'a [u8]) -> Self {
+            self.body = Some(Body::Bytes(Cow::Borrowed(body)));
+            self
+        }
+
+        /// Sends `pairs` as an `application/x-www-form-urlencoded` body.
+        pub fn form(mut self, pairs: &[(&str, &str)]) -> Self {
+            let form = pairs
+                .iter()
+                .fold(QueryBuilder::new(), |form, &(key, value)| {
+                    form.pair(key, value)
+                })
+                .build();
+            self.set_header("Content-Type", "application/x-www-form-urlencoded");
+            self.body = Some(Body::Bytes(Cow::Owned(form.into_bytes())));
+            self
+        }
+
+        /// Serializes `value` as an `application/json` body. A serialization
+        /// failure is reported by `send`.
+        #[cfg(feature = "json")]
+        pub fn json<T: serde::Serialize + ?Sized>(mut self, value: &T) -> Self {
+            match serde_json::to_vec(value) {
+                Ok(body) => {
+                    self.set_header("Content-Type", "application/json");
+                    self.body = Some(Body::Bytes(Cow::Owned(body)));
+                }
+                Err(error) => {
+                    self.error.get_or_insert(error.into());
+                }
+            }
+            self
+        }
+
+        /// Sends `Expect: 100-continue`, so a server can turn the request
+        /// down on its headers alone. WinINet doesn't surface the interim
+        /// 100, so a streamed body still follows the headers at once; what
+        /// this adds is that when the server refuses early, say with 417 or
+        /// 413, and drops the connection mid-upload, `send` stops writing and
+        /// returns that response instead of a write error.
+        pub fn expect_continue(mut self) -> Self {
+            self.set_header("Expect", "100-continue");
+            self
+        }
+
+        /// Streams the file at `path` as the body, with its size as the
+        /// `Content-Length`. Unless a `Content-Type` was already set, one is
+        /// picked from the extension, defaulting to
+        /// `application/octet-stream`. A file that can't be opened makes
+        /// `send` fail before connecting.
+        pub fn body_from_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+            let path = path.as_ref();
+            let file = File::open(path).and_then(|file| Ok((file.metadata()?.len(), file)));
+            match file {
+                Ok((length, file)) => {
+                    if !self.has_header("Content-Type") {
+                        self.set_header("Content-Type", content_type_for(path));
+                    }
+                    self.body = Some(Body::Reader(Box::new(file), Some(length)));
+                }
+                Err(error) => {
+                    self.error.get_or_insert(error.into());
+                }
+            }
+            self
+        }
+
+        pub fn multipart(mut self, form: Multipart) -> Self {
+            self.set_header("Content-Type", &form.content_type());
+            self.body = Some(Body::Bytes(Cow::Owned(form.into_body())));
+            self
+        }
+
+        /// Streams the body from `reader` instead of holding it in memory.
+        /// With a `length` it is sent with that `Content-Length`, otherwise
+        /// with chunked transfer encoding. A reader that yields more or fewer
+        /// bytes than `length` fails the request with `InvalidInput`.
+        pub fn body_from_reader<R: io::Read + 'a>(
+            mut self,
+            reader: R,
+            length: Option<u64>,
+        ) -> Self {
+            self.body = Some(Body::Reader(Box::new(reader), length));
+            self
+        }
+
+        /// Bounds connecting, sending and each read of this request.
+        pub fn timeout(mut self, timeout: Duration) -> Self {
+            self.timeout = Some(timeout);
+            self
+        }
+
+        /// Asks for bytes `start..=end` only, or everything from `start` on
+        /// when `end` is `None`.
+        pub fn range(mut self, start: u64, end: Option<u64>) -> Self {
+            let end = end.map(|end| end.to_string()).unwrap_or_default();
+            self.set_header("Range", &format!("bytes={}-{}", start, end));
+            self
+        }
+
+        pub fn no_redirect(mut self) -> Self {
+            self.flags |= INTERNET_FLAG_NO_AUTO_REDIRECT;
+            self
+        }
+
+        /// Follows at most `max` redirects itself instead of leaving them to
+        /// WinINet, failing with `HttpError::TooManyRedirects` beyond that or
+        /// as soon as a `Location` repeats an earlier URL. 303s, and 301s and
+        /// 302s answering a POST, continue as GET without the body; other
+        /// redirects resend it, except a streamed body, whose redirect
+        /// response is returned as is.
+        pub fn max_redirects(mut self, max: u32) -> Self {
+            self.flags |= INTERNET_FLAG_NO_AUTO_REDIRECT;
+            self.max_redirects = Some(max);
+            self
+        }
+
+        /// Asks the server for the body only if it changed after `time`; an
+        /// unchanged resource comes back as `304 Not Modified`.
+        pub fn if_modified_since(mut self, time: SystemTime) -> Self {
+            self.set_header("If-Modified-Since", &http_date::format(time));
+            self
+        }
+
+        /// Asks the server for the body only if its entity tag no longer
+        /// matches `etag`, which is sent as given, quotes included.
+        pub fn if_none_match(self, etag: &str) -> Self {
+            self.header("If-None-Match", etag)
+        }
+
+        /// Advertises `Accept-Encoding: gzip, deflate` and has WinINet inflate
+        /// the body as it is read, so `read`, `as_bytes` and `text` all see
+        /// the decoded bytes. Identity responses pass through untouched.
+        pub fn auto_decompress(mut self, enable: bool) -> Self {
+            if enable {
+                self.set_header("Accept-Encoding", "gzip, deflate");
+            }
+            self.auto_decompress = enable;
+            self
+        }
+
+        /// Presents `cert` if the server asks for a client certificate.
+        pub fn client_cert(mut self, cert: &ClientCert) -> Self {
+            self.client_cert = Some(cert.clone());
+            self
+        }
+
+        /// Overrides the handle's cache policy for this request.
+        pub fn cache_policy(mut self, policy: CachePolicy) -> Self {
+            self.cache_policy = Some(policy);
+            self
+        }
+
+        /// ORs raw `INTERNET_FLAG_*` values, such as `INTERNET_FLAG_NO_COOKIES`
+        /// or `INTERNET_FLAG_KEEP_CONNECTION`, into the `HttpOpenRequestW`
+        /// flags. The defaults stay in place; this can only add to them.
+        pub fn extra_flags(mut self, flags: DWORD) -> Self {
+            self.flags |= flags;
+            self
+        }
+
+        /// Skips certificate validation for this request only: untrusted
+        /// issuers, mismatched host names and expired certificates are all
+        /// accepted. This leaves the connection open to interception, so use
+        /// it only against servers you control, such as test hosts with
+        /// self-signed certificates.
+        pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+            const CERT_FLAGS: DWORD =
+                INTERNET_FLAG_IGNORE_CERT_CN_INVALID | INTERNET_FLAG_IGNORE_CERT_DATE_INVALID;
+            const SECURITY_FLAGS: DWORD = SECURITY_FLAG_IGNORE_UNKNOWN_CA
+                | SECURITY_FLAG_IGNORE_CERT_CN_INVALID
+                | SECURITY_FLAG_IGNORE_CERT_DATE_INVALID
+                | SECURITY_FLAG_IGNORE_REVOCATION;
+            if accept {
+                self.flags |= CERT_FLAGS;
+                self.security_flags |= SECURITY_FLAGS;
+            } else {
+                self.flags &= !CERT_FLAGS;
+                self.security_flags &= !SECURITY_FLAGS;
+            }
+            self
+        }
+
+        /// Adds an `Authorization: Basic` header. The credentials are UTF-8
+        /// encoded, and a missing password is sent as an empty one.
+        pub fn basic_auth(mut self, user: &str, pass: Option<&str>) -> Self {
+            self.set_header("Authorization", &basic_auth_header(user, pass));
+            self
+        }
+
+        /// Answers a 401 carrying a `Digest` challenge by resending the
+        /// request with an `Authorization: Digest` header for `user` and
+        /// `pass`. MD5 and SHA-256, with or without `-sess`, and `qop=auth`
+        /// are supported. A streamed body can't be resent, so its 401 is
+        /// returned as is. The nonce is kept on the connection: later
+        /// requests, redirects and retries answer it up front, each with the
+        /// next nonce count.
+        pub fn digest_auth(mut self, user: &str, pass: &str) -> Self {
+            self.digest = Some((user.to_string(), pass.to_string()));
+            self
+        }
+
+        fn has_header(&self, name: &str) -> bool {
+            self.headers.split_terminator("\r\n").any(|line| {
+                line.split(':')
+                    .next()
+                    .is_some_and(|line_name| line_name.trim().eq_ignore_ascii_case(name))
+            })
+        }
+
+        fn set_header(&mut self, name: &str, value: &str) {
+            self.remove_header(name);
+            self.push_header(name, value);
+        }
+
+        fn remove_header(&mut self, name: &str) {
+            self.headers = self
+                .headers
+                .split_terminator("\r\n")
+                .filter(|line| {
+                    !line
+                        .split(':')
+                        .next()
+                        .is_some_and(|line_name| line_name.trim().eq_ignore_ascii_case(name))
+                })
+                .map(|line| format!("{}\r\n", line))
+                .collect();
+        }
+
+        fn push_header(&mut self, name: &str, value: &str) {
+            self.headers.push_str(name);
+            self.headers.push_str(": ");
+            self.headers.push_str(value);
+            self.headers.push_str("\r\n");
+        }
+
+        /// Sends `agent` as the `User-Agent` of this request only, instead of
+        /// the one given to `Internet::open`.
+        pub fn user_agent(self, agent: &str) -> Self {
+            self.replace_header("User-Agent", agent)
+        }
+
+        /// Sends `Host: host` instead of the name the request connects to.
+        /// Together with `connect_to` this reaches a particular server while
+        /// presenting another site's name, e.g. to check a machine before a
+        /// DNS cutover. The TLS server name and certificate check still go by
+        /// the connected name, so an https check by IP usually also needs
+        /// `danger_accept_invalid_certs`.
+        pub fn host_header(self, host: &str) -> Self {
+            self.replace_header("Host", host)
+        }
+
+        /// Neither sends stored cookies with this request nor stores the
+        /// `Set-Cookie` headers of its response. Other requests on the handle
+        /// keep using the cookie store.
+        pub fn no_cookies(mut self) -> Self {
+            self.flags |= INTERNET_FLAG_NO_COOKIES;
+            self
+        }
+
+        /// With `true`, sets `INTERNET_FLAG_KEEP_CONNECTION` and sends
+        /// `Connection: keep-alive`, so requests on one `Connection` or `Pool`
+        /// entry reuse the socket and its TLS session. With `false`, sends
+        /// `Connection: close` and the socket is dropped after the response.
+        pub fn keep_alive(mut self, keep: bool) -> Self {
+            if keep {
+                self.flags |= INTERNET_FLAG_KEEP_CONNECTION;
+                self.replace_header("Connection", "keep-alive")
+            } else {
+                self.flags &= !INTERNET_FLAG_KEEP_CONNECTION;
+                self.replace_header("Connection", "close")
+            }
+        }
+
+        /// Opens the connection to `host`, typically an IP address, instead of
+        /// the host in the URL, keeping the same port and scheme. The `Host`
+        /// header follows `host` unless `host_header` says otherwise.
+        pub fn connect_to(mut self, host: &str) -> Self {
+            if let Some(connection) = self.connection.take() {
+                let session = connection
+                    .handle
+                    ._parent
+                    .clone()
+                    .expect("a connection handle has a session parent");
+                match Connection::open(
+                    &session,
+                    host,
+                    connection.port,
+                    connection.secure,
+                    connection.settings.clone(),
+                ) {
+                    Ok(connection) => self.connection = Some(connection),
+                    Err(error) => {
+                        self.error.get_or_insert(error);
+                    }
+                }
+            }
+            self
+        }
+
+        fn replace_header(mut self, name: &'static str, value: &str) -> Self {
+            match check_header(name, value) {
+                Ok(()) => {
+                    self.replace_headers
+                        .retain(|(existing, _)| *existing != name);
+                    self.replace_headers.push((name, value.to_string()));
+                }
+                Err(error) => {
+                    self.error.get_or_insert(error);
+                }
+            }
+            self
+        }
+
+        /// Re-sends the request up to `retries` more times when it fails with
+        /// a transient error, waiting `base_delay`, then twice that, and so on.
+        pub fn retry(self, retries: u32, base_delay: Duration) -> Self {
+            self.retry_policy(RetryPolicy::new(retries, base_delay))
+        }
+
+        pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+            self.retry = Some(policy);
+            self
+        }
+
+        /// Sends the request with a hard wall-clock limit: once `deadline`
+        /// passes, the request handle is closed and whatever is blocked on it,
+        /// sending or reading the body, fails with `ERROR_INTERNET_TIMEOUT`.
+        /// Unlike `timeout`, which bounds each socket wait, this also stops a
+        /// server that keeps trickling data.
+        pub fn send_with_deadline(mut self, deadline: Instant) -> Result<Response, HttpError> {
+            self.deadline = Some(deadline);
+            self.send()
+        }
+
+        pub fn send(mut self) -> Result<Response, HttpError> {
+            let connection = match (self.error.take(), self.connection.take()) {
+                (None, Some(connection)) => connection,
+                (Some(error), _) => return Err(error),
+                (None, None) => unreachable!("a builder without a connection carries its error"),
+            };
+            let body = self.body.take();
+            match self.max_redirects {
+                Some(max) => self.follow_redirects(connection, body, max),
+                None => self.send_authenticated(&connection, body),
+            }
+        }
+
+        fn follow_redirects(
+            mut self,
+            mut connection: Connection,
+            mut body: Option<Body<'a>>,
+            max: u32,
+        ) -> Result<Response, HttpError> {
+            let mut urls = vec![connection.url(&self.path)];
+            loop {
+                // Only a body held in memory can be sent again.
+                let streamed = matches!(body, Some(Body::Reader(..)));
+                let mut resend = match &body {
+                    Some(Body::Bytes(bytes)) => Some(Body::Bytes(bytes.clone())),
+                    _ => None,
+                };
+                let response = self.send_authenticated(&connection, body.take())?;
+                let location = match response.header("Location") {
+                    Some(location) if response.is_redirect() => location,
+                    _ => return Ok(response),
+                };
+                let status = response.status();
+                if status == 303
+                    || (matches!(status, 301 | 302) && self.method.eq_ignore_ascii_case("POST"))
+                {
+                    self.method = "GET".to_string();
+                    resend = None;
+                    for name in ["Content-Type", "Content-Length", "Transfer-Encoding"] {
+                        self.remove_header(name);
+                    }
+                } else if streamed {
+                    return Ok(response);
+                }
+                let url = combine_url(&urls[urls.len() - 1], location.trim())?;
+                let repeated = urls.contains(&url);
+                urls.push(url);
+                if repeated || urls.len() - 1 > max as usize {
+                    return Err(HttpError::TooManyRedirects { urls });
+                }
+
+                let target = Url::parse(&urls[urls.len() - 1])?;
+                if (target.secure, target.port) != (connection.secure, connection.port)
+                    || !target.host.eq_ignore_ascii_case(&connection.host)
+                {
+                    // Credentials and cookies are only for the origin they
+                    // were given to.
+                    for name in ["Authorization", "Proxy-Authorization", "Cookie"] {
+                        self.remove_header(name);
+                    }
+                    self.digest = None;
+                    let session = connection
+                        .handle
+                        ._parent
+                        .clone()
+                        .expect("a connection handle has a session parent");
+                    connection = Connection::open(
+                        &session,
+                        &target.host,
+                        target.port,
+                        target.secure,
+                        connection.settings.clone(),
+                    )?;
+                }
+                self.path = target.path;
+                body = resend;
+            }
+        }
+
+        fn send_authenticated(
+            &mut self,
+            connection: &Connection,
+            body: Option<Body<'a>>,
+        ) -> Result<Response, HttpError> {
+            if self.digest.is_none() {
+                return self.send_to(connection, body);
+            }
+            let streamed = matches!(body, Some(Body::Reader(..)));
+            let resend = match &body {
+                Some(Body::Bytes(bytes)) => Some(Body::Bytes(bytes.clone())),
+                _ => None,
+            };
+            let response = self.send_to(connection, body)?;
+            if response.status() != 401 || streamed {
+                return Ok(response);
+            }
+            let challenge = response
+                .www_authenticate()
+                .iter()
+                .filter_map(|challenge| challenge.parse::<Challenge>().ok())
+                .find(|challenge| challenge.scheme.eq_ignore_ascii_case("Digest"));
+            let challenge = match challenge {
+                Some(challenge) => challenge,
+                None => return Ok(response),
+            };
+            *connection.digest.lock().unwrap() = Some(DigestNonce {
+                challenge,
+                cnonce: format!("{:016x}", RandomState::new().build_hasher().finish()),
+                count: 0,
+            });
+            self.send_to(connection, resend)
+        }
+
+        /// Sets `Authorization` from the connection's Digest nonce, if this
+        /// request has credentials for it, using the nonce's next count.
+        fn answer_digest(&mut self, connection: &Connection) -> Result<(), HttpError> {
+            let (user, pass) = match &self.digest {
+                Some(credentials) => credentials,
+                None => return Ok(()),
+            };
+            let mut nonce = connection.digest.lock().unwrap();
+            let nonce = match nonce.as_mut() {
+                Some(nonce) => nonce,
+                None => return Ok(()),
+            };
+            nonce.count += 1;
+            let authorization = digest_authorization(
+                &nonce.challenge,
+                &nonce.cnonce,
+                nonce.count,
+                user,
+                pass,
+                &self.method,
+                &self.path,
+            )?;
+            self.set_header("Authorization", &authorization);
+            Ok(())
+        }
+
+        fn send_to(
+            &mut self,
+            connection: &Connection,
+            body: Option<Body<'a>>,
+        ) -> Result<Response, HttpError> {
+            match &body {
+                Some(Body::Reader(_, None)) => self.set_header("Transfer-Encoding", "chunked"),
+                // Totals beyond a DWORD can't go through INTERNET_BUFFERS.
+                Some(Body::Reader(_, Some(length))) if *length > DWORD::MAX as u64 => {
+                    self.set_header("Content-Length", &length.to_string())
+                }
+                _ => {}
+            }
+            self.answer_digest(connection)?;
+
+            check_headers(&self.headers)?;
+            let logger = connection.settings.logger.clone();
+            let url = logger.as_ref().map(|logger| {
+                let url = connection.url(&self.path);
+                logger.log(LogEvent::Request {
+                    method: &self.method,
+                    url: &url,
+                    headers: &logger.headers(&self.headers),
+                    body: match &body {
+                        Some(Body::Bytes(body)) if logger.bodies => Some(body),
+                        _ => None,
+                    },
+                });
+                url
+            });
+            let url = url.as_deref().unwrap_or("");
+            let mut headers = if self.headers.is_empty() {
+                None
+            } else {
+                Some(wide(&self.headers, "headers")?)
+            };
+            let body = match body {
+                Some(Body::Reader(mut reader, length)) => {
+                    // A reader can't be rewound, so streamed bodies are never retried.
+                    let response = self.open_request(connection)?;
+                    let early_reply = self.has_header("Expect");
+                    send_streaming(
+                        response.handle.raw,
+                        headers.as_ref(),
+                        &mut reader,
+                        length,
+                        early_reply,
+                    )
+                    .map_err(|error| response.handle.closed_error().unwrap_or(error))?;
+                    return Ok(response.logged(logger.as_ref(), url));
+                }
+                Some(Body::Bytes(body)) => Some(body),
+                None => None,
+            };
+            let body = body.as_deref();
+
+            let retry = self
+                .retry
+                .clone()
+                .filter(|policy| policy.applies_to(&self.method));
+            let mut attempt = 0;
+            loop {
+                let result = self.open_request(connection).and_then(|response| {
+                    send_request(response.handle.raw, headers.as_ref(), body)
+                        .map_err(|error| response.handle.closed_error().unwrap_or(error))?;
+                    Ok(response)
+                });
+                let expired = self
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() >= deadline);
+                match (result, &retry) {
+                    (Err(error), Some(policy))
+                        if !expired && policy.should_retry(attempt, &error) =>
+                    {
+                        std::thread::sleep(policy.delay(attempt));
+                        attempt += 1;
+                        // A resent nonce count would be taken for a replay.
+                        if self.digest.is_some() {
+                            self.answer_digest(connection)?;
+                            headers = Some(wide(&self.headers, "headers")?);
+                        }
+                    }
+                    (result, _) => {
+                        return result.map(|response| response.logged(logger.as_ref(), url))
+                    }
+                }
+            }
+        }
+
+        fn open_request(&self, connection: &Connection) -> Result<Response, HttpError> {
+            let method = wide(&self.method, "method")?;
+            let path = wide(&self.path, "path")?;
+            let handle = unsafe {
+                HttpOpenRequestW(
+                    connection.handle.raw,
+                    method.as_ptr(),
+                    path.as_ptr(),
+                    null(),
+                    null(),
+                    null_mut(),
+                    self.cache_policy
+                        .unwrap_or(connection.settings.cache_policy)
+                        .flags()
+                        | if connection.secure {
+                            INTERNET_FLAG_SECURE
+                        } else {
+                            0
+                        }
+                        | self.flags,
+                    0,
+                )
+            };
+            let response = Response {
+                handle: Arc::new(Handle::new(handle, Some(connection.handle.clone()))?),
+                decoded: self.auto_decompress,
+                raw: self.flags & INTERNET_FLAG_RAW_DATA != 0,
+                reader: OnceLock::new(),
+                log: None,
+            };
+            if self.auto_decompress {
+                set_dword_option(response.handle.raw, INTERNET_OPTION_HTTP_DECODING, 1)?;
+            }
+
+            if let Some(cert) = &self.client_cert {
+                let result = unsafe {
+                    InternetSetOptionW(
+                        response.handle.raw,
+                        INTERNET_OPTION_CLIENT_CERT_CONTEXT,
+                        cert.0 as *mut winapi::ctypes::c_void,
+                        std::mem::size_of::<CERT_CONTEXT>() as DWORD,
+                    )
+                };
+                if result == 0 {
+                    return Err(HttpError::last_error());
+                }
+            }
+            if let Some(deadline) = self.deadline {
+                Handle::watch(&response.handle, deadline);
+            }
+
+            if self.security_flags != 0 {
+                set_dword_option(
+                    response.handle.raw,
+                    INTERNET_OPTION_SECURITY_FLAGS,
+                    self.security_flags,
+                )?;
+            }
+
+            for (name, value) in &self.replace_headers {
+                // Headers passed to HttpSendRequestW are only added, so ones
+                // WinINet fills in itself have to be replaced explicitly.
+                let header = wide(&format!("{}: {}\r\n", name, value), "headers")?;
+                let result = unsafe {
+                    HttpAddRequestHeadersW(
+                        response.handle.raw,
+                        header.as_ptr(),
+                        0xFFFFFFFF,
+                        HTTP_ADDREQ_FLAG_ADD | HTTP_ADDREQ_FLAG_REPLACE,
+                    )
+                };
+                if result == 0 {
+                    return Err(HttpError::last_error());
+                }
+            }
+
+            if let Some(timeout) = self.timeout {
+                let millis = duration_millis(timeout);
+                for &option in &[
+                    INTERNET_OPTION_CONNECT_TIMEOUT,
+                    INTERNET_OPTION_SEND_TIMEOUT,
+                    INTERNET_OPTION_RECEIVE_TIMEOUT,
+                ] {
+                    set_dword_option(response.handle.raw, option, millis)?;
+                }
+            }
+            Ok(response)
+        }
+    }
+
+    /// When and how often `RequestBuilder::send` re-issues a failed request.
+    /// Only transport failures are retried; any response that arrives,
+    /// whatever its status, is returned as is.
+    #[derive(Debug, Clone)]
+    pub struct RetryPolicy {
+        retries: u32,
+        base_delay: Duration,
+        codes: Vec<DWORD>,
+        non_idempotent: bool,
+    }
+
+    impl RetryPolicy {
+        /// Retries timeouts, resets, aborted and refused connections.
+        pub fn new(retries: u32, base_delay: Duration) -> RetryPolicy {
+            RetryPolicy {
+                retries,
+                base_delay,
+                codes: vec![
+                    ERROR_INTERNET_TIMEOUT,
+                    ERROR_INTERNET_CONNECTION_RESET,
+                    ERROR_INTERNET_CONNECTION_ABORTED,
+                    ERROR_INTERNET_CANNOT_CONNECT,
+                ],
+                non_idempotent: false,
+            }
+        }
+
+        /// Replaces the Win32 error codes that count as transient.
+        pub fn codes(mut self, codes: &[u32]) -> RetryPolicy {
+            self.codes = codes.to_vec();
+            self
+        }
+
+        /// Allows retrying methods such as POST, PUT and PATCH, which could
+        /// apply twice if the first attempt reached the server.
+        pub fn allow_non_idempotent(mut self, allow: bool) -> RetryPolicy {
+            self.non_idempotent = allow;
+            self
+        }
+
+        fn applies_to(&self, method: &str) -> bool {
+            self.non_idempotent
+                || ["GET", "HEAD", "OPTIONS", "TRACE", "DELETE"]
+                    .iter()
+                    .any(|idempotent| method.eq_ignore_ascii_case(idempotent))
+        }
+
+        fn should_retry(&self, attempt: u32, error: &HttpError) -> bool {
+            attempt < self.retries
+                && matches!(error, HttpError::Win32 { .. })
+                && self.codes.contains(&error.code())
+        }
+
+        fn delay(&self, attempt: u32) -> Duration {
+            self.base_delay
+                .checked_mul(1 << attempt.min(16))
+                .unwrap_or(Duration::MAX)
+        }
+    }
+
+    fn send_request(
+        request: HINTERNET,
+        headers: Option<&WideString>,
+        body: Option<&[u8]>,
+    ) -> Result<(), HttpError> {
+        // WinINet always emits Content-Length for POST, so an empty body
+        // goes out as `Content-Length: 0`.
+        let result = unsafe {
+            HttpSendRequestW(
+                request,
+                headers.map_or(null(), |headers| headers.as_ptr()),
+                if headers.is_some() { 0xFFFFFFFF } else { 0 },
+                body.map_or(null_mut(), |body| {
+                    body.as_ptr() as *mut winapi::ctypes::c_void
+                }),
+                body.map_or(0, |body| body.len() as DWORD),
+            )
+        };
+        if result == 0 {
+            Err(HttpError::last_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn content_type_for(path: &Path) -> &'static str {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("txt") => "text/plain",
+            Some("htm" | "html") => "text/html",
+            Some("css") => "text/css",
+            Some("csv") => "text/csv",
+            Some("js") => "text/javascript",
+            Some("json") => "application/json",
+            Some("xml") => "application/xml",
+            Some("pdf") => "application/pdf",
+            Some("zip") => "application/zip",
+            Some("gz") => "application/gzip",
+            Some("png") => "image/png",
+            Some("jpg" | "jpeg") => "image/jpeg",
+            Some("gif") => "image/gif",
+            Some("svg") => "image/svg+xml",
+            Some("webp") => "image/webp",
+            _ => "application/octet-stream",
+        }
+    }
+
+    /// Resolves `relative`, e.g. a `Location` header, against `base`.
+    fn combine_url(base: &str, relative: &str) -> Result<String, HttpError> {
+        let base = wide(base, "URL")?;
+        let relative = wide(relative, "URL")?;
+        let mut buffer = vec![0u16; 256];
+        loop {
+            let mut len = buffer.len() as DWORD;
+            let result = unsafe {
+                InternetCombineUrlW(
+                    base.as_ptr(),
+                    relative.as_ptr(),
+                    buffer.as_mut_ptr(),
+                    &mut len,
+                    0,
+                )
+            };
+            if result != 0 {
+                return Ok(String::from_utf16_lossy(&buffer[..len as usize]));
+            }
+            if unsafe { GetLastError() } != ERROR_INSUFFICIENT_BUFFER {
+                return Err(HttpError::last_error());
+            }
+            buffer.resize((len as usize + 1).max(buffer.len() * 2), 0);
+        }
+    }
+
+    /// With `early_reply`, a failed write, typically the server rejecting
+    /// the request and closing before the body is through, is answered by
+    /// reading the server's response instead.
+    fn send_streaming(
+        request: HINTERNET,
+        headers: Option<&WideString>,
+        reader: &mut dyn io::Read,
+        length: Option<u64>,
+        early_reply: bool,
+    ) -> Result<(), HttpError> {
+        let mut buffers: INTERNET_BUFFERSW = unsafe { std::mem::zeroed() };
+        buffers.dwStructSize = std::mem::size_of::<INTERNET_BUFFERSW>() as DWORD;
+        if let Some(headers) = headers {
+            buffers.lpcszHeader = headers.as_ptr();
+            buffers.dwHeadersLength = (headers.len() - 1) as DWORD;
+            buffers.dwHeadersTotal = buffers.dwHeadersLength;
+        }
+        buffers.dwBufferTotal = match length {
+            Some(length) if length <= DWORD::MAX as u64 => length as DWORD,
+            _ => 0,
+        };
+        if unsafe { HttpSendRequestExW(request, &mut buffers, null_mut(), 0, 0) } == 0 {
+            return Err(HttpError::last_error());
+        }
+
+        match upload_body(request, reader, length) {
+            Err(HttpError::Win32 { .. }) if early_reply => {}
+            result => result?,
+        }
+        if unsafe { HttpEndRequestW(request, null_mut(), 0, 0) } == 0 {
+            Err(HttpError::last_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn upload_body(
+        request: HINTERNET,
+        reader: &mut dyn io::Read,
+        length: Option<u64>,
+    ) -> Result<(), HttpError> {
+        // WinINet doesn't frame chunked uploads itself.
+        let chunked = length.is_none();
+        let mut buffer = vec![0u8; BUFFER_SIZE];
+        let mut sent: u64 = 0;
+        loop {
+            let read_size = match reader.read(&mut buffer) {
+                Ok(read_size) => read_size,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error.into()),
+            };
+            sent += read_size as u64;
+            match length {
+                Some(length) if sent > length => {
+                    return Err(HttpError::InvalidInput(format!(
+                        "body longer than its declared {} bytes",
+                        length
+                    )))
+                }
+                Some(length) if read_size == 0 && sent < length => {
+                    return Err(HttpError::InvalidInput(format!(
+                        "body ended after {} of its declared {} bytes",
+                        sent, length
+                    )))
+                }
+                _ => {}
+            }
+            if chunked {
+                write_all(request, format!("{:x}\r\n", read_size).as_bytes())?;
+            }
+            write_all(request, &buffer[..read_size])?;
+            if chunked {
+                write_all(request, b"\r\n")?;
+            }
+            if read_size == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn write_all(request: HINTERNET, mut data: &[u8]) -> Result<(), HttpError> {
+        while !data.is_empty() {
+            let mut written: DWORD = 0;
+            let result = unsafe {
+                InternetWriteFile(
+                    request,
+                    data.as_ptr() as *const winapi::ctypes::c_void,
+                    data.len().min(DWORD::MAX as usize) as DWORD,
+                    &mut written,
+                )
+            };
+            if result == 0 {
+                return Err(HttpError::last_error());
+            }
+            data = &data[written as usize..];
+        }
+        Ok(())
+    }
+
+    fn check_header(name: &str, value: &str) -> Result<(), HttpError> {
+        let name_ok = !name.is_empty() && name.bytes().all(|b| b.is_ascii_graphic() && b != b':');
+        if !name_ok {
+            return Err(HttpError::InvalidInput(format!("header name {:?}", name)));
+        }
+        if value.contains(['\r', '\n', '\0']) {
+            return Err(HttpError::InvalidInput(format!(
+                "header value for {} contains CR, LF or NUL",
+                name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks a raw, CRLF-separated header block: every line must be a
+    /// `Name: value` pair and no CR or LF may appear outside a line break.
+    fn check_headers(headers: &str) -> Result<(), HttpError> {
+        for line in headers.split_terminator("\r\n") {
+            match line.split_once(':') {
+                Some((name, value)) => check_header(name.trim_end(), value)?,
+                None => {
+                    return Err(HttpError::InvalidInput(format!(
+                        "header line {:?} has no colon",
+                        line
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    static DEFAULT_AGENT: OnceLock<String> = OnceLock::new();
+    static DEFAULT_INTERNET: OnceLock<Internet> = OnceLock::new();
+
+    /// Sets the agent of the handle behind `get` and `post`. It must be
+    /// called before their first use; afterwards the agent is fixed and this
+    /// fails.
+    pub fn set_default_agent(agent: &str) -> Result<(), HttpError> {
+        DEFAULT_AGENT
+            .set(agent.to_string())
+            .map_err(|_| HttpError::InvalidInput("the default agent is already in use".to_string()))
+    }
+
+    /// The process-wide handle the one-shot functions share, opened on first
+    /// use with the agent from `set_default_agent`, or `thin-http/<version>`.
+    pub fn default_internet() -> Result<&'static Internet, HttpError> {
+        if let Some(internet) = DEFAULT_INTERNET.get() {
+            return Ok(internet);
+        }
+        loop {
+            let agent = DEFAULT_AGENT
+                .get()
+                .cloned()
+                .unwrap_or_else(|| concat!("thin-http/", env!("CARGO_PKG_VERSION")).to_string());
+            // The agent is only fixed once a handle opened with it, so a
+            // failed open leaves `set_default_agent` usable. Threads racing
+            // here each open a handle; all but the first stored are simply
+            // closed again, and one opened under an agent set meanwhile is
+            // opened afresh.
+            let internet = Internet::open(&agent, None)?;
+            if *DEFAULT_AGENT.get_or_init(|| agent.clone()) == agent {
+                return Ok(DEFAULT_INTERNET.get_or_init(|| internet));
+            }
+        }
+    }
+
+    /// Fetches `url` through the `default_internet` handle.
+    pub fn get(url: &str) -> Result<Response, HttpError> {
+        default_internet()?.get(url, None)
+    }
+
+    /// Posts `body` to `url` through the `default_internet` handle.
+    pub fn post(url: &str, body: &[u8]) -> Result<Response, HttpError> {
+        default_internet()?.post(url, None, body)
+    }
+
+    /// An agent string such as `myapp/1.2.3 (Windows NT 10.0; x86_64)` for
+    /// `Internet::open`, with the version of the running Windows.
+    pub fn default_agent(app: &str, version: &str) -> String {
+        let os = match windows_version() {
+            Some((major, minor)) => format!("Windows NT {}.{}", major, minor),
+            None => "Windows NT".to_string(),
+        };
+        format!("{}/{} ({}; {})", app, version, os, std::env::consts::ARCH)
+    }
+
+    /// The real `(major, minor)` Windows version. `GetVersionExW` reports
+    /// 6.2 to unmanifested programs on anything newer, so this asks ntdll.
+    fn windows_version() -> Option<(u32, u32)> {
+        type RtlGetVersion = unsafe extern "system" fn(*mut RTL_OSVERSIONINFOW) -> LONG;
+        let ntdll = unsafe { GetModuleHandleW(WideString::from("ntdll.dll").as_ptr()) };
+        if ntdll.is_null() {
+            return None;
+        }
+        let function = unsafe { GetProcAddress(ntdll, b"RtlGetVersion\0".as_ptr() as *const _) };
+        if function.is_null() {
+            return None;
+        }
+        let function: RtlGetVersion = unsafe { std::mem::transmute(function) };
+        let mut info: RTL_OSVERSIONINFOW = unsafe { std::mem::zeroed() };
+        info.dwOSVersionInfoSize = std::mem::size_of::<RTL_OSVERSIONINFOW>() as DWORD;
+        if unsafe { function(&mut info) } != 0 {
+            return None;
+        }
+        Some((info.dwMajorVersion, info.dwMinorVersion))
+    }
+
+    /// Cookies WinINet's per-process store would send to `url`, formatted as
+    /// a `Cookie` header value.
+    pub fn get_cookie(url: &str) -> Option<String> {
+        let url = wide(url, "URL").ok()?;
+        let mut buffer = vec![0u16; 256];
+        loop {
+            let mut size = buffer.len() as DWORD;
+            let result =
+                unsafe { InternetGetCookieW(url.as_ptr(), null(), buffer.as_mut_ptr(), &mut size) };
+            if result != 0 {
+                let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+                return Some(String::from_utf16_lossy(&buffer[..len]));
+            }
+            if unsafe { GetLastError() } != ERROR_INSUFFICIENT_BUFFER {
+                return None;
+            }
+            // The required size may come back in bytes or characters, so
+            // treat it as characters to be safe.
+            buffer.resize((size as usize + 1).max(buffer.len() * 2), 0);
+        }
+    }
+
+    /// Stores `cookie` (e.g. `"name=value; path=/"`) for `url` in WinINet's
+    /// per-process store, where later requests pick it up.
+    pub fn set_cookie(url: &str, cookie: &str) -> Result<(), HttpError> {
+        let url = wide(url, "URL")?;
+        let cookie = wide(cookie, "cookie")?;
+        let result = unsafe { InternetSetCookieW(url.as_ptr(), null(), cookie.as_ptr()) };
+        if result == 0 {
+            Err(HttpError::last_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Expires every cookie WinINet would send to `url`. Cookies are
+    /// overwritten at the root path and at the default path for `url`, so
+    /// one scoped to some other path or domain can survive.
+    pub fn clear_cookies_for(url: &str) -> Result<(), HttpError> {
+        let cookies = match get_cookie(url) {
+            Some(cookies) => cookies,
+            None => return Ok(()),
+        };
+        for cookie in cookies.split(';') {
+            let name = cookie.split('=').next().unwrap_or("").trim();
+            if name.is_empty() {
+                continue;
+            }
+            let expired = format!("{}=; expires=Thu, 01 Jan 1970 00:00:00 GMT", name);
+            set_cookie(url, &format!("{}; path=/", expired))?;
+            set_cookie(url, &expired)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes every cookie in WinINet's store, persistent ones from disk
+    /// and session ones from memory.
+    pub fn clear_cookies() -> Result<(), HttpError> {
+        delete_cache_entries(Some("cookie:"))?;
+        let result = unsafe {
+            InternetSetOptionW(
+                null_mut(),
+                INTERNET_OPTION_END_BROWSER_SESSION,
+                null_mut(),
+                0,
+            )
+        };
+        if result == 0 {
+            Err(HttpError::last_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Deletes every entry in WinINet's URL cache. Entries in use elsewhere
+    /// are left alone.
+    pub fn clear_cache() -> Result<(), HttpError> {
+        delete_cache_entries(None)
+    }
+
+    fn delete_cache_entries(pattern: Option<&str>) -> Result<(), HttpError> {
+        let pattern = pattern.map(WideString::from);
+        // u64s keep the entry's pointer fields aligned.
+        let mut buffer = vec![0u64; 512];
+        let mut urls = Vec::new();
+        let mut find: HANDLE = null_mut();
+        loop {
+            let mut size = (buffer.len() * 8) as DWORD;
+            let entry = buffer.as_mut_ptr() as LPINTERNET_CACHE_ENTRY_INFOW;
+            let found = unsafe {
+                if find.is_null() {
+                    find = FindFirstUrlCacheEntryW(
+                        pattern.as_ref().map_or(null(), |pattern| pattern.as_ptr()),
+                        entry,
+                        &mut size,
+                    );
+                    !find.is_null()
+                } else {
+                    FindNextUrlCacheEntryW(find, entry, &mut size) != 0
+                }
+            };
+            if found {
+                let url = unsafe { (*entry).lpszSourceUrlName };
+                if !url.is_null() {
+                    let len = (0..).take_while(|&i| unsafe { *url.add(i) } != 0).count();
+                    urls.push(unsafe { std::slice::from_raw_parts(url, len + 1) }.to_vec());
+                }
+                continue;
+            }
+            match unsafe { GetLastError() } {
+                ERROR_INSUFFICIENT_BUFFER => buffer.resize((size as usize).div_ceil(8), 0),
+                ERROR_NO_MORE_ITEMS => break,
+                _ => {
+                    let error = HttpError::last_error();
+                    if !find.is_null() {
+                        unsafe { FindCloseUrlCache(find) };
+                    }
+                    return Err(error);
+                }
+            }
+        }
+        if !find.is_null() {
+            unsafe { FindCloseUrlCache(find) };
+        }
+        for url in urls {
+            unsafe { DeleteUrlCacheEntryW(url.as_ptr()) };
+        }
+        Ok(())
+    }
+
+    /// The `Authorization` value answering a Digest `challenge`, per RFC 7616.
+    fn digest_authorization(
+        challenge: &Challenge,
+        cnonce: &str,
+        count: u32,
+        user: &str,
+        pass: &str,
+        method: &str,
+        uri: &str,
+    ) -> Result<String, HttpError> {
+        let algorithm = challenge.param("algorithm").unwrap_or("MD5");
+        let (algorithm_id, sess) = match algorithm.to_ascii_uppercase().as_str() {
+            "MD5" => (CALG_MD5, false),
+            "MD5-SESS" => (CALG_MD5, true),
+            "SHA-256" => (CALG_SHA_256, false),
+            "SHA-256-SESS" => (CALG_SHA_256, true),
+            _ => {
+                return Err(HttpError::InvalidInput(format!(
+                    "digest algorithm {:?}",
+                    algorithm
+                )))
+            }
+        };
+        // Without a qop the legacy RFC 2069 response is computed; auth-int
+        // alone would need the body hashed, which isn't supported.
+        let qop = match challenge.param("qop") {
+            None => false,
+            Some(qop)
+                if qop
+                    .split(',')
+                    .any(|qop| qop.trim().eq_ignore_ascii_case("auth")) =>
+            {
+                true
+            }
+            Some(qop) => return Err(HttpError::InvalidInput(format!("digest qop {:?}", qop))),
+        };
+        let hash = |data: String| hex_hash(algorithm_id, data.as_bytes());
+        let realm = challenge.param("realm").unwrap_or("");
+        let nonce = challenge
+            .param("nonce")
+            .ok_or_else(|| HttpError::InvalidInput("digest challenge without a nonce".into()))?;
+        let nc = format!("{:08x}", count);
+
+        let mut ha1 = hash(format!("{}:{}:{}", user, realm, pass))?;
+        if sess {
+            ha1 = hash(format!("{}:{}:{}", ha1, nonce, cnonce))?;
+        }
+        let ha2 = hash(format!("{}:{}", method, uri))?;
+        let response = if qop {
+            hash(format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2))?
+        } else {
+            hash(format!("{}:{}:{}", ha1, nonce, ha2))?
+        };
+
+        let quote = |value: &str| value.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut header = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, response=\"{}\"",
+            quote(user),
+            quote(realm),
+            quote(nonce),
+            quote(uri),
+            algorithm,
+            response
+        );
+        if let Some(opaque) = challenge.param("opaque") {
+            header.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+        }
+        if qop {
+            header.push_str(&format!(", qop=auth, nc={}, cnonce=\"{}\"", nc, cnonce));
+        }
+        Ok(header)
+    }
+
+    /// `data` hashed with a CryptoAPI `algorithm`, as lowercase hex.
+    fn hex_hash(algorithm: ALG_ID, data: &[u8]) -> Result<String, HttpError> {
+        let mut provider: HCRYPTPROV = 0;
+        let acquired = unsafe {
+            CryptAcquireContextW(
+                &mut provider,
+                null(),
+                null(),
+                PROV_RSA_AES,
+                CRYPT_VERIFYCONTEXT,
+            )
+        };
+        if acquired == 0 {
+            return Err(HttpError::last_error());
+        }
+        let mut hash: HCRYPTHASH = 0;
+        let mut digest = [0u8; 32];
+        let mut len = digest.len() as DWORD;
+        let hashed = unsafe {
+            CryptCreateHash(provider, algorithm, 0, 0, &mut hash) != 0
+                && CryptHashData(hash, data.as_ptr(), data.len() as DWORD, 0) != 0
+                && CryptGetHashParam(hash, HP_HASHVAL, digest.as_mut_ptr(), &mut len, 0) != 0
+        };
+        let error = if hashed {
+            None
+        } else {
+            Some(HttpError::last_error())
+        };
+        unsafe {
+            if hash != 0 {
+                CryptDestroyHash(hash);
+            }
+            CryptReleaseContext(provider, 0);
+        }
+        match error {
+            Some(error) => Err(error),
+            None => Ok(digest[..len as usize]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect()),
+        }
+    }
+
+    /// The `Authorization` header value `basic_auth` sends.
+    pub fn basic_auth_header(user: &str, pass: Option<&str>) -> String {
+        let credentials = format!("{}:{}", user, pass.unwrap_or(""));
+        format!("Basic {}", base64::encode(credentials.as_bytes()))
+    }
+
+    fn duration_millis(duration: Duration) -> DWORD {
+        duration.as_millis().min(DWORD::MAX as u128) as DWORD
+    }
+
+    fn set_dword_option(handle: HINTERNET, option: DWORD, value: DWORD) -> Result<(), HttpError> {
+        set_option(handle, option, &value.to_ne_bytes())
+    }
+
+    fn set_option(handle: HINTERNET, option: DWORD, value: &[u8]) -> Result<(), HttpError> {
+        // WinINet only reads the buffer, whatever the pointer type says.
+        let result = unsafe {
+            InternetSetOptionW(
+                handle,
+                option,
+                value.as_ptr() as *mut winapi::ctypes::c_void,
+                value.len() as DWORD,
+            )
+        };
+        if result == 0 {
+            Err(HttpError::last_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn set_string_option(handle: HINTERNET, option: DWORD, value: &str) -> Result<(), HttpError> {
+        let value = wide(value, "option value")?;
+        let result = unsafe {
+            InternetSetOptionW(
+                handle,
+                option,
+                value.as_ptr() as *mut winapi::ctypes::c_void,
+                (value.len() - 1) as DWORD,
+            )
+        };
+        if result == 0 {
+            Err(HttpError::last_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn query_string_option(handle: HINTERNET, option: DWORD) -> Option<String> {
+        let mut buffer = vec![0u16; 256];
+        loop {
+            let mut len = (buffer.len() * 2) as DWORD;
+            let result = unsafe {
+                InternetQueryOptionW(
+                    handle,
+                    option,
+                    buffer.as_mut_ptr() as *mut winapi::ctypes::c_void,
+                    &mut len,
+                )
+            };
+            if result != 0 {
+                return WideString::try_from(&buffer[..])
+                    .ok()
+                    .map(|url| url.to_string_lossy());
+            }
+            if unsafe { GetLastError() } != ERROR_INSUFFICIENT_BUFFER {
+                return None;
+            }
+            // `len` is the required size in bytes.
+            let required = len as usize / 2 + 1;
+            buffer.resize(required.max(buffer.len() * 2), 0);
+        }
+    }
+
+    fn query_option(handle: HINTERNET, option: DWORD) -> Result<Vec<u8>, HttpError> {
+        let mut buffer = vec![0u8; 64];
+        loop {
+            let mut len = buffer.len() as DWORD;
+            let result = unsafe {
+                InternetQueryOptionW(
+                    handle,
+                    option,
+                    buffer.as_mut_ptr() as *mut winapi::ctypes::c_void,
+                    &mut len,
+                )
+            };
+            if result != 0 {
+                buffer.truncate(len as usize);
+                return Ok(buffer);
+            }
+            if unsafe { GetLastError() } != ERROR_INSUFFICIENT_BUFFER {
+                return Err(HttpError::last_error());
+            }
+            // `len` is the required size in bytes.
+            buffer.resize((len as usize).max(buffer.len() * 2), 0);
+        }
+    }
+
+    fn query_dword_option(handle: HINTERNET, option: DWORD) -> Option<DWORD> {
+        let mut value: DWORD = 0;
+        let mut len = std::mem::size_of::<DWORD>() as DWORD;
+        let result = unsafe {
+            InternetQueryOptionW(
+                handle,
+                option,
+                (&mut value as *mut DWORD) as *mut winapi::ctypes::c_void,
+                &mut len,
+            )
+        };
+        if result == 0 {
+            None
+        } else {
+            Some(value)
+        }
+    }
+
+    // Not in winapi: the INTERNET_OPTION_SECURITY_CONNECTION_INFO payload.
+    const INTERNET_OPTION_SECURITY_CONNECTION_INFO: DWORD = 66;
+
+    #[repr(C)]
+    #[allow(non_snake_case)]
+    struct INTERNET_SECURITY_CONNECTION_INFO {
+        dwSize: DWORD,
+        fSecure: BOOL,
+        connectionInfo: SecPkgContext_ConnectionInfo,
+        cipherInfo: SecPkgContext_CipherInfo,
+    }
+
+    /// What a TLS connection negotiated, from `Response::tls_info`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct TlsInfo {
+        /// `"TLS 1.2"`, `"TLS 1.3"` and so on, or `"unknown"`.
+        pub protocol: &'static str,
+        /// The IANA name, e.g. `TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384`.
+        pub cipher_suite: String,
+        /// Bulk cipher key length in bits.
+        pub cipher_strength: u32,
+    }
+
+    /// A certificate, with its private key, from the Windows certificate
+    /// store for servers that require mutual TLS. Pass it to
+    /// `RequestBuilder::client_cert`.
+    #[derive(Debug)]
+    pub struct ClientCert(PCCERT_CONTEXT);
+
+    impl ClientCert {
+        /// Looks up a certificate in the current user's personal (`MY`) store
+        /// by its SHA-1 thumbprint, as shown in certmgr.msc or by
+        /// `Get-ChildItem Cert:\CurrentUser\My`. Spaces and colons between
+        /// the hex digits are ignored.
+        pub fn from_thumbprint(thumbprint: &str) -> Result<ClientCert, HttpError> {
+            let digits: Vec<u8> = thumbprint
+                .bytes()
+                .filter(|b| !matches!(b, b' ' | b':'))
+                .collect();
+            let mut hash = digits
+                .chunks(2)
+                .map(|pair| {
+                    std::str::from_utf8(pair)
+                        .ok()
+                        .filter(|pair| pair.len() == 2)
+                        .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                })
+                .collect::<Option<Vec<u8>>>()
+                .filter(|hash| hash.len() == 20)
+                .ok_or_else(|| HttpError::InvalidInput(format!("thumbprint {:?}", thumbprint)))?;
+
+            let store = unsafe { CertOpenSystemStoreW(0, WideString::from("MY").as_ptr()) };
+            if store.is_null() {
+                return Err(HttpError::last_error());
+            }
+            let blob = CRYPT_HASH_BLOB {
+                cbData: hash.len() as DWORD,
+                pbData: hash.as_mut_ptr(),
+            };
+            let context = unsafe {
+                CertFindCertificateInStore(
+                    store,
+                    X509_ASN_ENCODING | PKCS_7_ASN_ENCODING,
+                    0,
+                    CERT_FIND_HASH,
+                    &blob as *const CRYPT_HASH_BLOB as *const winapi::ctypes::c_void,
+                    null(),
+                )
+            };
+            let result = if context.is_null() {
+                Err(HttpError::last_error())
+            } else {
+                Ok(ClientCert(context))
+            };
+            // The context keeps its own reference to the store.
+            unsafe { CertCloseStore(store, 0) };
+            result
+        }
+
+        /// Wraps a context obtained elsewhere, e.g. from
+        /// `CertFindCertificateInStore`. The context is duplicated, so the
+        /// caller still frees its own copy.
+        ///
+        /// # Safety
+        ///
+        /// `context` must be a valid certificate context.
+        pub unsafe fn from_raw(context: PCCERT_CONTEXT) -> ClientCert {
+            ClientCert(CertDuplicateCertificateContext(context))
+        }
+
+        pub fn as_raw(&self) -> PCCERT_CONTEXT {
+            self.0
+        }
+    }
+
+    impl Clone for ClientCert {
+        fn clone(&self) -> Self {
+            ClientCert(unsafe { CertDuplicateCertificateContext(self.0) })
+        }
+    }
+
+    impl Drop for ClientCert {
+        fn drop(&mut self) {
+            unsafe { CertFreeCertificateContext(self.0) };
+        }
+    }
+
+    // SAFETY: certificate contexts are reference counted by CryptoAPI and
+    // safe to use and free from any thread.
+    unsafe impl Send for ClientCert {}
+    unsafe impl Sync for ClientCert {}
+
+    fn protocol_name(protocol: DWORD) -> &'static str {
+        // Each version has a client and a server bit; match either.
+        match protocol {
+            p if p & 0x3000 != 0 => "TLS 1.3",
+            p if p & 0x0C00 != 0 => "TLS 1.2",
+            p if p & 0x0300 != 0 => "TLS 1.1",
+            p if p & 0x00C0 != 0 => "TLS 1.0",
+            p if p & 0x0030 != 0 => "SSL 3.0",
+            _ => "unknown",
+        }
+    }
+
+    const BUFFER_SIZE: usize = 16 * 1024;
+
+    // Most a Content-Length is trusted for up front; past this the body
+    // buffer grows as data actually arrives.
+    const MAX_PREALLOCATION: u64 = 1 << 20;
+
+    fn body_capacity(length: Option<u64>) -> usize {
+        // The extra chunk leaves room for the final zero-length read.
+        length.unwrap_or(0).min(MAX_PREALLOCATION) as usize + BUFFER_SIZE
+    }
+
+    pub struct Bytes<'a> {
+        handle: &'a Response,
+        data: Vec<u8>,
+        index: usize,
+        size: usize,
+        yielded: u64,
+    }
+
+    impl<'a> Bytes<'a> {
+        fn new(handle: &'a Response, capacity: usize) -> Bytes<'a> {
+            Bytes {
+                handle,
+                data: vec![0; capacity.max(1)],
+                index: 0,
+                size: 0,
+                yielded: 0,
+            }
+        }
+
+        /// How many bytes the iterator has yielded so far. Bytes read ahead
+        /// into its buffer but not yet returned aren't counted.
+        pub fn bytes_read(&self) -> u64 {
+            self.yielded
+        }
+    }
+
+    impl<'a> Iterator for Bytes<'a> {
+        type Item = u8;
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.index >= self.size {
+                match self.handle.read_into(&mut self.data) {
+                    Ok(read_size) if read_size > 0 => {
+                        self.size = read_size;
+                        self.index = 0;
+                    }
+                    _ => return None,
+                }
+            }
+            let byte = self.data[self.index];
+            self.index += 1;
+            self.yielded += 1;
+            Some(byte)
+        }
+    }
+
+    /// A body reader that owns its response, from `Response::into_reader`,
+    /// so it can be returned or stored without a borrow. The handle is
+    /// closed when the reader is dropped.
+    pub struct ResponseReader {
+        response: Response,
+    }
+
+    impl ResponseReader {
+        /// The response being read, for its status and headers.
+        pub fn response(&self) -> &Response {
+            &self.response
+        }
+    }
+
+    impl io::Read for ResponseReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.response.read_into(buf)
+        }
+    }
+
+    impl io::Read for Response {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.read_into(buf)
+        }
+    }
+
+    impl io::Read for &Response {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.read_into(buf)
+        }
+    }
+
+    fn code_page(charset: &str) -> Option<UINT> {
+        match charset {
+            "shift_jis" | "shift-jis" | "sjis" | "x-sjis" | "windows-31j" | "cp932" => Some(932),
+            "euc-jp" => Some(20932),
+            "iso-2022-jp" => Some(50220),
+            "windows-1252" | "iso-8859-1" | "latin1" | "us-ascii" => Some(1252),
+            _ => None,
+        }
+    }
+
+    /// Decodes a body for `text`: a byte order mark wins, then `charset`,
+    /// then UTF-8. The mark itself is dropped.
+    fn decode_text(body: &[u8], charset: Option<&str>) -> String {
+        if let Some(rest) = body.strip_prefix(b"\xEF\xBB\xBF") {
+            return String::from_utf8_lossy(rest).into_owned();
+        }
+        if let Some(rest) = body.strip_prefix(b"\xFF\xFE") {
+            return decode_utf16(rest, u16::from_le_bytes);
+        }
+        if let Some(rest) = body.strip_prefix(b"\xFE\xFF") {
+            return decode_utf16(rest, u16::from_be_bytes);
+        }
+        match charset {
+            // Without a mark, "utf-16" is taken to be little-endian, the way
+            // Windows writes it.
+            Some("utf-16" | "utf-16le") => decode_utf16(body, u16::from_le_bytes),
+            Some("utf-16be") => decode_utf16(body, u16::from_be_bytes),
+            _ => match charset.and_then(code_page) {
+                Some(code_page) => decode(body, code_page),
+                None => String::from_utf8_lossy(body).into_owned(),
+            },
+        }
+    }
+
+    fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> String {
+        let pairs = bytes.chunks_exact(2);
+        let odd = !pairs.remainder().is_empty();
+        let units = pairs.map(|pair| unit([pair[0], pair[1]]));
+        let mut text: String = char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        if odd {
+            text.push(char::REPLACEMENT_CHARACTER);
+        }
+        text
+    }
+
+    fn decode(bytes: &[u8], code_page: UINT) -> String {
+        if bytes.is_empty() {
+            return String::new();
+        }
+        let len = unsafe {
+            MultiByteToWideChar(
+                code_page,
+                0,
+                bytes.as_ptr() as LPCSTR,
+                bytes.len() as c_int,
+                null_mut(),
+                0,
+            )
+        };
+        let mut wide = vec![0u16; len as usize];
+        unsafe {
+            MultiByteToWideChar(
+                code_page,
+                0,
+                bytes.as_ptr() as LPCSTR,
+                bytes.len() as c_int,
+                wide.as_mut_ptr(),
+                len,
+            );
+        }
+        String::from_utf16_lossy(&wide)
+    }
+
+    fn parse_refresh(value: &str) -> Option<(Duration, String)> {
+        let value = value.trim();
+        let digits = value
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(value.len());
+        let (delay, rest) = if digits == 0 {
+            (Duration::ZERO, value)
+        } else {
+            let delay = Duration::from_secs(value[..digits].parse().ok()?);
+            // Fractions of a second are ignored, as browsers do.
+            let rest = value[digits..].trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+            let rest = rest.trim_start();
+            (
+                delay,
+                rest.strip_prefix([';', ',']).unwrap_or(rest).trim_start(),
+            )
+        };
+        let target = match rest.get(..3) {
+            Some(key)
+                if key.eq_ignore_ascii_case("url") && rest[3..].trim_start().starts_with('=') =>
+            {
+                rest[3..].trim_start()[1..].trim_start()
+            }
+            _ => rest,
+        };
+        let target = match target.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let target = &target[1..];
+                target.split(quote).next().unwrap_or(target)
+            }
+            _ => target,
+        }
+        .trim();
+        if target.is_empty() {
+            None
+        } else {
+            Some((delay, target.to_string()))
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ContentRange {
+        pub start: u64,
+        pub end: u64,
+        /// The full length, unless the server sent `*`.
+        pub total: Option<u64>,
+    }
+
+    impl ContentRange {
+        fn parse(value: &str) -> Option<ContentRange> {
+            let value = value.trim();
+            let (unit, range) = value.split_once(' ')?;
+            if !unit.eq_ignore_ascii_case("bytes") {
+                return None;
+            }
+            let (range, total) = range.trim().split_once('/')?;
+            let (start, end) = range.split_once('-')?;
+            Some(ContentRange {
+                start: start.trim().parse().ok()?,
+                end: end.trim().parse().ok()?,
+                total: match total.trim() {
+                    "*" => None,
+                    total => Some(total.parse().ok()?),
+                },
+            })
+        }
+    }
+
+    /// A parsed `Content-Type` header value.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ContentType {
+        /// Lowercased `type/subtype`, e.g. `text/html`.
+        pub media_type: String,
+        /// Lowercased `charset` parameter, without quotes.
+        pub charset: Option<String>,
+    }
+
+    impl std::str::FromStr for ContentType {
+        type Err = HttpError;
+
+        fn from_str(value: &str) -> Result<ContentType, HttpError> {
+            let (media_type, mut parameters) = value.split_once(';').unwrap_or((value, ""));
+            let media_type = media_type.trim();
+            if media_type
+                .split('/')
+                .filter(|part| !part.is_empty())
+                .count()
+                != 2
+            {
+                return Err(HttpError::InvalidInput(format!(
+                    "media type {:?}",
+                    media_type
+                )));
+            }
+            let mut charset = None;
+            while !parameters.is_empty() {
+                let (key, rest) = parameters.split_once('=').unwrap_or((parameters, ""));
+                let rest = rest.trim_start();
+                let (value, rest) = match rest.strip_prefix('"') {
+                    // A quoted string runs to the next unescaped quote.
+                    Some(quoted) => {
+                        let mut value = String::new();
+                        let mut chars = quoted.char_indices();
+                        let mut end = quoted.len();
+                        while let Some((i, c)) = chars.next() {
+                            match c {
+                                '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                                '"' => {
+                                    end = i + 1;
+                                    break;
+                                }
+                                c => value.push(c),
+                            }
+                        }
+                        let rest = &quoted[end..];
+                        (value, rest.split_once(';').map_or("", |(_, rest)| rest))
+                    }
+                    None => {
+                        let (value, rest) = rest.split_once(';').unwrap_or((rest, ""));
+                        (value.trim().to_string(), rest)
+                    }
+                };
+                if key.trim().eq_ignore_ascii_case("charset") && charset.is_none() {
+                    charset = Some(value.to_ascii_lowercase());
+                }
+                parameters = rest;
+            }
+            Ok(ContentType {
+                media_type: media_type.to_ascii_lowercase(),
+                charset,
+            })
+        }
+    }
+
+    /// One parsed `WWW-Authenticate` or `Proxy-Authenticate` challenge, e.g.
+    /// `Bearer realm="x", error="invalid_token"`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Challenge {
+        /// The scheme as sent, e.g. `Digest`.
+        pub scheme: String,
+        /// A bare token in place of parameters, as `Negotiate` sends.
+        pub token: Option<String>,
+        /// Parameters in order, names lowercased and values unquoted.
+        pub params: Vec<(String, String)>,
+    }
+
+    impl Challenge {
+        /// The value of parameter `name`, ignoring case.
+        pub fn param(&self, name: &str) -> Option<&str> {
+            self.params
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        }
+    }
+
+    impl std::str::FromStr for Challenge {
+        type Err = HttpError;
+
+        fn from_str(value: &str) -> Result<Challenge, HttpError> {
+            let invalid = || HttpError::InvalidInput(format!("challenge {:?}", value));
+            let trimmed = value.trim();
+            let (scheme, rest) = trimmed
+                .split_once(|c: char| c.is_ascii_whitespace())
+                .unwrap_or((trimmed, ""));
+            if scheme.is_empty() || scheme.contains([',', '=', '"']) {
+                return Err(invalid());
+            }
+            let mut challenge = Challenge {
+                scheme: scheme.to_string(),
+                token: None,
+                params: Vec::new(),
+            };
+            let mut rest = rest.trim();
+            let token68 = |c: char| c.is_ascii_alphanumeric() || "-._~+/".contains(c);
+            if !rest.is_empty() && rest.trim_end_matches('=').chars().all(token68) {
+                challenge.token = Some(rest.to_string());
+                return Ok(challenge);
+            }
+            while !rest.is_empty() {
+                let (key, after) = rest.split_once('=').ok_or_else(invalid)?;
+                let after = after.trim_start();
+                let (value, after) = match after.strip_prefix('"') {
+                    Some(quoted) => {
+                        let mut value = String::new();
+                        let mut chars = quoted.char_indices();
+                        let mut end = None;
+                        while let Some((i, c)) = chars.next() {
+                            match c {
+                                '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                                '"' => {
+                                    end = Some(i + 1);
+                                    break;
+                                }
+                                c => value.push(c),
+                            }
+                        }
+                        let after = &quoted[end.ok_or_else(invalid)?..];
+                        (value, after.split_once(',').map_or("", |(_, after)| after))
+                    }
+                    None => {
+                        let (value, after) = after.split_once(',').unwrap_or((after, ""));
+                        (value.trim().to_string(), after)
+                    }
+                };
+                challenge
+                    .params
+                    .push((key.trim().to_ascii_lowercase(), value));
+                rest = after.trim_start_matches(|c: char| c == ',' || c.is_ascii_whitespace());
+            }
+            Ok(challenge)
+        }
+    }
+
+    #[cfg(feature = "gzip")]
+    fn is_zlib(body: &[u8]) -> bool {
+        body.len() >= 2
+            && body[0] & 0x0F == 8
+            && (u16::from(body[0]) << 8 | u16::from(body[1])) % 31 == 0
+    }
+
+    impl Response {
+        fn logged(mut self, logger: Option<&Arc<Logger>>, url: &str) -> Response {
+            if let Some(logger) = logger {
+                let status = self.status() as u16;
+                logger.log(LogEvent::Response {
+                    url,
+                    status,
+                    content_length: self.content_length(),
+                });
+                self.log = Some(ResponseLog {
+                    logger: logger.clone(),
+                    url: url.to_string(),
+                    status,
+                    bytes: AtomicU64::new(0),
+                    done: AtomicBool::new(false),
+                });
+            }
+            self
+        }
+
+        /// Reads the next piece of the body into `buf` and returns how many
+        /// bytes were written, 0 at the end. This is the single
+        /// `InternetReadFile` that `Read`, `chunks` and the other readers are
+        /// built on, and it takes `&self`, so a caller can reuse one buffer
+        /// without wrapping the response in anything.
+        pub fn read_into(&self, buf: &mut [u8]) -> io::Result<usize> {
+            let read_size = io::Read::read(&mut *self.reader().lock().unwrap(), buf)?;
+            if let Some(log) = &self.log {
+                log.read(&buf[..read_size]);
+            }
+            Ok(read_size)
+        }
+
+        fn reader(&self) -> &Mutex<chunked::Reader<RawBody>> {
+            self.reader.get_or_init(|| {
+                let framed = chunked::is_framed(
+                    self.raw,
+                    self.query_info(HTTP_QUERY_TRANSFER_ENCODING, None)
+                        .as_deref(),
+                );
+                Mutex::new(chunked::Reader::new(RawBody(self.handle.clone()), framed))
+            })
+        }
+
+        /// The header fields sent after the last chunk, once the body has
+        /// been read to the end. Only requests made with
+        /// `INTERNET_FLAG_RAW_DATA` (through `get_with_flags` or
+        /// `extra_flags`) have them: otherwise WinINet removes the chunk
+        /// framing itself and drops the trailers, and this is always empty.
+        pub fn trailers(&self) -> Vec<(String, String)> {
+            self.reader().lock().unwrap().trailers().to_vec()
+        }
+
+        fn is_chunked(&self) -> bool {
+            self.query_info(HTTP_QUERY_TRANSFER_ENCODING, None)
+                .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"))
+        }
+
+        /// The body split into lines as they arrive, without the trailing
+        /// `\n` or `\r\n`. Suits newline-delimited JSON and other long-lived
+        /// streams, since each line is yielded as soon as it's complete.
+        pub fn lines(self) -> io::Lines<io::BufReader<Response>> {
+            io::BufRead::lines(io::BufReader::new(self))
+        }
+
+        /// Aborts the response from any thread by closing its request handle.
+        /// A read blocked in another thread returns promptly, and it and every
+        /// later read fail with `HttpError::Cancelled` (wrapped in the
+        /// `io::Error` for the `io::Result` readers). Share the response with
+        /// the reading thread through an `Arc` or `std::thread::scope`.
+        pub fn cancel(&self) {
+            self.handle.close();
+        }
+
+        /// Reads the whole body, sized up front from `Content-Length` when known.
+        /// A body cut short of its `Content-Length` fails with `UnexpectedEof`.
+        pub fn read_to_vec(&self) -> io::Result<Vec<u8>> {
+            let mut body = Vec::with_capacity(body_capacity(self.content_length()));
+            self.read_rest(&mut body)?;
+            Ok(body)
+        }
+
+        /// Like `read_to_vec`, but keeps whatever arrived when the read fails,
+        /// e.g. on a `timeout` or deadline partway through, and hands it back
+        /// alongside the error. The handle is closed as usual when `self` is
+        /// dropped.
+        pub fn read_partial(&self) -> (Vec<u8>, io::Result<()>) {
+            let mut body = Vec::new();
+            let result = self.read_rest(&mut body);
+            (body, result)
+        }
+
+        /// Calls `f` with each piece of the body as it arrives, reusing one
+        /// buffer, so a parser can be fed without holding the whole body.
+        /// An error from `f` stops the read and is returned.
+        pub fn for_each_chunk<F: FnMut(&[u8]) -> io::Result<()>>(
+            &self,
+            mut f: F,
+        ) -> io::Result<()> {
+            let mut buffer = vec![0u8; BUFFER_SIZE];
+            let mut read = 0u64;
+            loop {
+                let read_size = self.read_into(&mut buffer)?;
+                if read_size == 0 {
+                    return self.check_complete(read);
+                }
+                read += read_size as u64;
+                f(&buffer[..read_size])?;
+            }
+        }
+
+        // Appends the rest of the body to `body`, which keeps what was read
+        // even on failure.
+        fn read_rest(&self, body: &mut Vec<u8>) -> io::Result<()> {
+            let start = body.len();
+            loop {
+                let filled = body.len();
+                body.resize(filled + BUFFER_SIZE, 0);
+                let read_size = match self.read_into(&mut body[filled..]) {
+                    Ok(read_size) => read_size,
+                    Err(error) => {
+                        body.truncate(filled);
+                        return Err(error);
+                    }
+                };
+                body.truncate(filled + read_size);
+                if read_size == 0 {
+                    return self.check_complete((body.len() - start) as u64);
+                }
+            }
+        }
+
+        /// Fails with `UnexpectedEof` if `read` bytes fall short of the
+        /// `Content-Length`. Chunked and decoded bodies have no length to
+        /// check against.
+        fn check_complete(&self, read: u64) -> io::Result<()> {
+            // HEAD responses and 1xx, 204 and 304 replies have no body,
+            // whatever their Content-Length says.
+            let status = self.status();
+            let bodiless = status < 200
+                || status == 204
+                || status == 304
+                || self
+                    .query_info(HTTP_QUERY_REQUEST_METHOD, None)
+                    .is_some_and(|method| method.eq_ignore_ascii_case("HEAD"));
+            match self.content_length() {
+                Some(length) if !bodiless && read < length => Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("body ended after {} of {} bytes", read, length),
+                )),
+                _ => Ok(()),
+            }
+        }
+
+        /// Like `read_to_vec`, but fails with `InvalidData` once the body
+        /// grows past `max` bytes, or before reading anything if
+        /// `Content-Length` already says it will.
+        pub fn read_to_vec_limited(&self, max: usize) -> io::Result<Vec<u8>> {
+            let too_large = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("body exceeds {} bytes", max),
+                )
+            };
+            let length = self.content_length();
+            if length.is_some_and(|length| length > max as u64) {
+                return Err(too_large());
+            }
+            let mut body = Vec::with_capacity(body_capacity(length));
+            loop {
+                // Asking for one byte past `max` tells an exact fit from an overrun.
+                let filled = body.len();
+                let wanted = BUFFER_SIZE.min(max.saturating_add(1) - filled);
+                body.resize(filled + wanted, 0);
+                let read_size = self.read_into(&mut body[filled..])?;
+                body.truncate(filled + read_size);
+                if body.len() > max {
+                    return Err(too_large());
+                }
+                if read_size == 0 {
+                    self.check_complete(body.len() as u64)?;
+                    return Ok(body);
+                }
+            }
+        }
+
+        /// Streams the body into the file at `path`, returning the number of
+        /// bytes written. The data goes to `<path>.part` first and is renamed
+        /// into place only once the whole body has arrived.
+        pub fn save_to<P: AsRef<Path>>(&self, path: P) -> io::Result<u64> {
+            self.save_to_with_progress(path, |_, _| {})
+        }
+
+        /// Like `save_to`, calling `progress(downloaded, total)` after each
+        /// chunk is written. `total` is the `Content-Length`, so it is `None`
+        /// for chunked responses.
+        pub fn save_to_with_progress<P, F>(&self, path: P, mut progress: F) -> io::Result<u64>
+        where
+            P: AsRef<Path>,
+            F: FnMut(u64, Option<u64>),
+        {
+            let path = path.as_ref();
+            let total = self.content_length();
+            let mut partial = path.as_os_str().to_owned();
+            partial.push(".part");
+            let partial = Path::new(&partial);
+
+            let result = File::create(partial).and_then(|mut file| {
+                self.write_body(&mut file, |written| progress(written, total))
+            });
+            match result {
+                Ok(written) => {
+                    fs::rename(partial, path)?;
+                    Ok(written)
+                }
+                Err(error) => {
+                    let _ = fs::remove_file(partial);
+                    Err(error)
+                }
+            }
+        }
+
+        fn write_body<F: FnMut(u64)>(
+            &self,
+            writer: &mut dyn Write,
+            mut progress: F,
+        ) -> io::Result<u64> {
+            let mut buffer = vec![0u8; BUFFER_SIZE];
+            let mut written = 0u64;
+            loop {
+                let read_size = self.read_into(&mut buffer)?;
+                if read_size == 0 {
+                    self.check_complete(written)?;
+                    return Ok(written);
+                }
+                writer.write_all(&buffer[..read_size])?;
+                written += read_size as u64;
+                progress(written);
+            }
+        }
+
+        /// The parsed `Content-Range` header of a `206 Partial Content` reply.
+        pub fn content_range(&self) -> Option<ContentRange> {
+            ContentRange::parse(&self.header("Content-Range")?)
+        }
+
+        /// The `Last-Modified` header as a time, in any HTTP date format.
+        pub fn last_modified(&self) -> Option<SystemTime> {
+            http_date::parse(&self.header("Last-Modified")?)
+        }
+
+        /// The `ETag` header as sent, quotes and any `W/` prefix included, so
+        /// it can be passed straight back to `if_none_match`.
+        pub fn etag(&self) -> Option<String> {
+            self.header("ETag").map(|etag| etag.trim().to_string())
+        }
+
+        /// How long a 429 or 503 asks the client to wait, from a `Retry-After`
+        /// of either seconds or an HTTP date. Dates already past give zero.
+        pub fn retry_after(&self) -> Option<Duration> {
+            let value = self.header("Retry-After")?;
+            let value = value.trim();
+            match value.parse() {
+                Ok(seconds) => Some(Duration::from_secs(seconds)),
+                Err(_) => Some(
+                    http_date::parse(value)?
+                        .duration_since(SystemTime::now())
+                        .unwrap_or(Duration::ZERO),
+                ),
+            }
+        }
+
+        /// The delay and target of a `Refresh` header, in the
+        /// `5; url=/next` form or as a bare URL, which means no delay. A
+        /// header that only reloads the page yields `None`. The target may be
+        /// relative to `final_url`.
+        pub fn refresh(&self) -> Option<(Duration, String)> {
+            parse_refresh(&self.header("Refresh")?)
+        }
+
+        pub fn is_not_modified(&self) -> bool {
+            self.status_code()
+                .is_ok_and(|status_code| status_code == 304)
+        }
+
+        pub fn is_partial_content(&self) -> bool {
+            self.status_code()
+                .is_ok_and(|status_code| status_code == 206)
+        }
+
+        /// The `Content-Type` header, or `None` if it's missing or malformed.
+        pub fn content_type(&self) -> Option<ContentType> {
+            self.header("Content-Type")?.parse().ok()
+        }
+
+        /// Reads the whole body and decodes it. A leading UTF-8 or UTF-16
+        /// byte order mark decides the encoding and is stripped; otherwise
+        /// the `Content-Type` charset does. Anything unrecognised is treated
+        /// as UTF-8, lossily.
+        pub fn text(&self) -> io::Result<String> {
+            let body = self.read_to_vec()?;
+            let charset = self
+                .content_type()
+                .and_then(|content_type| content_type.charset);
+            Ok(decode_text(&body, charset.as_deref()))
+        }
+
+        /// The request handle, for WinINet calls this crate doesn't wrap. It
+        /// is owned by `self` and closed when `self` is dropped or cancelled,
+        /// so don't close it or use it after that.
+        pub fn as_raw_handle(&self) -> HINTERNET {
+            self.handle.raw
+        }
+
+        /// Whether the body is being inflated on the fly by `auto_decompress`.
+        /// The `Content-Encoding` header still names the original encoding,
+        /// and `content_length` is `None` since it counts encoded bytes.
+        pub fn is_decoded(&self) -> bool {
+            self.decoded
+                && self
+                    .header("Content-Encoding")
+                    .is_some_and(|encoding| !encoding.trim().eq_ignore_ascii_case("identity"))
+        }
+
+        /// Reads the whole body and undoes a gzip or deflate
+        /// `Content-Encoding`. Identity bodies are returned unchanged.
+        #[cfg(feature = "gzip")]
+        pub fn decoded_body(&self) -> io::Result<Vec<u8>> {
+            use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+            use std::io::Read;
+
+            let body = self.read_to_vec()?;
+            if self.decoded {
+                return Ok(body);
+            }
+            let encoding = self
+                .header("Content-Encoding")
+                .map(|encoding| encoding.trim().to_ascii_lowercase());
+            let mut decoded = Vec::new();
+            match encoding.as_deref() {
+                None | Some("") | Some("identity") => return Ok(body),
+                Some("gzip") | Some("x-gzip") => {
+                    GzDecoder::new(&body[..]).read_to_end(&mut decoded)?;
+                }
+                // "deflate" is meant to be zlib-wrapped, but some servers send
+                // a bare deflate stream.
+                Some("deflate") if is_zlib(&body) => {
+                    ZlibDecoder::new(&body[..]).read_to_end(&mut decoded)?;
+                }
+                Some("deflate") => {
+                    DeflateDecoder::new(&body[..]).read_to_end(&mut decoded)?;
+                }
+                Some(encoding) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unsupported Content-Encoding: {}", encoding),
+                    ))
+                }
+            }
+            Ok(decoded)
+        }
+
+        /// The body with a gzip or deflate `Content-Encoding` undone as it is
+        /// read, so a large download never sits in memory whole. Identity
+        /// bodies, and ones `auto_decompress` already inflates, pass through.
+        #[cfg(feature = "gzip")]
+        pub fn decoded_reader(&self) -> io::Result<Box<dyn io::Read + '_>> {
+            use flate2::bufread::{DeflateDecoder, GzDecoder, ZlibDecoder};
+            use std::io::BufRead;
+
+            if self.decoded {
+                return Ok(Box::new(self));
+            }
+            let encoding = self
+                .header("Content-Encoding")
+                .map(|encoding| encoding.trim().to_ascii_lowercase());
+            let mut body = io::BufReader::with_capacity(BUFFER_SIZE, self);
+            Ok(match encoding.as_deref() {
+                None | Some("") | Some("identity") => Box::new(body),
+                Some("gzip") | Some("x-gzip") => Box::new(GzDecoder::new(body)),
+                // Peek at the header to tell zlib-wrapped from bare deflate.
+                Some("deflate") if is_zlib(body.fill_buf()?) => Box::new(ZlibDecoder::new(body)),
+                Some("deflate") => Box::new(DeflateDecoder::new(body)),
+                Some(encoding) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unsupported Content-Encoding: {}", encoding),
+                    ))
+                }
+            })
+        }
+
+        /// Reads the whole body and deserializes it as JSON.
+        #[cfg(feature = "json")]
+        pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T, HttpError> {
+            Ok(serde_json::from_slice(&self.read_to_vec()?)?)
+        }
+
+        /// Moves the response into an owned `Read`, the counterpart of the
+        /// borrowing `as_bytes`.
+        pub fn into_reader(self) -> ResponseReader {
+            ResponseReader { response: self }
+        }
+
+        pub fn as_bytes(&self) -> Bytes<'_> {
+            self.bytes_with_capacity(BUFFER_SIZE)
+        }
+
+        /// The body as it arrives, in pieces of at most `size` bytes, one per
+        /// `InternetReadFile`. Far cheaper than `as_bytes` for large bodies,
+        /// and a failed read is yielded as an error rather than ending the
+        /// iteration quietly.
+        pub fn chunks(&self, size: usize) -> impl Iterator<Item = io::Result<Vec<u8>>> + '_ {
+            let mut buffer = vec![0; size.max(1)];
+            let mut done = false;
+            std::iter::from_fn(move || {
+                if done {
+                    return None;
+                }
+                match self.read_into(&mut buffer) {
+                    Ok(0) => {
+                        done = true;
+                        None
+                    }
+                    Ok(read_size) => Some(Ok(buffer[..read_size].to_vec())),
+                    Err(error) => {
+                        done = true;
+                        Some(Err(error))
+                    }
+                }
+            })
+        }
+
+        /// Like `as_bytes`, reading from WinINet `capacity` bytes at a time.
+        pub fn bytes_with_capacity(&self, capacity: usize) -> Bytes<'_> {
+            Bytes::new(self, capacity)
+        }
+
+        pub fn body(&self) -> Vec<u8> {
+            self.as_bytes().collect()
+        }
+
+        pub fn body_as_string(self) -> Result<String, std::string::FromUtf8Error> {
+            String::from_utf8(self.body())
+        }
+
+        pub fn status(&self) -> DWORD {
+            let mut status_code: DWORD = 0;
+            let mut len: DWORD = 4;
+            let mut index: DWORD = 0;
+            unsafe {
+                HttpQueryInfoW(
+                    self.handle.raw,
+                    HTTP_QUERY_STATUS_CODE | HTTP_QUERY_FLAG_NUMBER,
+                    (&mut status_code as *mut DWORD) as *mut winapi::ctypes::c_void,
+                    &mut len as *mut DWORD,
+                    &mut index as *mut DWORD,
+                );
+            };
+            status_code
+        }
+
+        /// Like `status`, but a failed query is reported instead of read as 0.
+        pub fn status_code(&self) -> Result<u16, HttpError> {
+            let mut status_code: DWORD = 0;
+            let mut len: DWORD = 4;
+            let mut index: DWORD = 0;
+            let result = unsafe {
+                HttpQueryInfoW(
+                    self.handle.raw,
+                    HTTP_QUERY_STATUS_CODE | HTTP_QUERY_FLAG_NUMBER,
+                    (&mut status_code as *mut DWORD) as *mut winapi::ctypes::c_void,
+                    &mut len as *mut DWORD,
+                    &mut index as *mut DWORD,
+                )
+            };
+            if result == 0 {
+                Err(HttpError::last_error())
+            } else {
+                Ok(status_code as u16)
+            }
+        }
+
+        /// The reason phrase, e.g. `"Not Found"`.
+        pub fn status_text(&self) -> Option<String> {
+            self.query_info(HTTP_QUERY_STATUS_TEXT, None)
+        }
+
+        pub fn is_success(&self) -> bool {
+            self.status_in(200..300)
+        }
+
+        pub fn is_redirect(&self) -> bool {
+            self.status_in(300..400)
+        }
+
+        pub fn is_client_error(&self) -> bool {
+            self.status_in(400..500)
+        }
+
+        pub fn is_server_error(&self) -> bool {
+            self.status_in(500..600)
+        }
+
+        /// Turns a 4xx or 5xx response into `HttpError::Status`, carrying up
+        /// to 512 bytes of the body, or a 407 into
+        /// `HttpError::ProxyAuthRequired`; anything else is handed back
+        /// unchanged.
+        pub fn error_for_status(self) -> Result<Response, HttpError> {
+            let status = self.status_code()?;
+            if !(400..600).contains(&status) {
+                return Ok(self);
+            }
+            if status == 407 {
+                return Err(HttpError::ProxyAuthRequired {
+                    challenges: self.proxy_authenticate(),
+                });
+            }
+            let mut snippet = Vec::new();
+            // The body is only context, so a failed read just leaves it short.
+            let _ = io::Read::read_to_end(&mut io::Read::take(self, 512), &mut snippet);
+            Err(HttpError::Status {
+                status,
+                body: String::from_utf8_lossy(&snippet).trim().to_string(),
+            })
+        }
+
+        /// The `WWW-Authenticate` challenges of a 401, one per header line;
+        /// parse each into a `Challenge` to read its parameters.
+        pub fn www_authenticate(&self) -> Vec<String> {
+            self.headers()
+                .filter(|(name, _)| name.trim().eq_ignore_ascii_case("WWW-Authenticate"))
+                .map(|(_, value)| value.trim().to_string())
+                .collect()
+        }
+
+        /// Whether a proxy refused the request until it gets credentials.
+        pub fn is_proxy_auth_required(&self) -> bool {
+            self.status_code()
+                .is_ok_and(|status_code| status_code == 407)
+        }
+
+        /// The `Proxy-Authenticate` challenges of a 407, e.g.
+        /// `Basic realm="corp"` or `NTLM`, one per header line.
+        pub fn proxy_authenticate(&self) -> Vec<String> {
+            self.headers()
+                .filter(|(name, _)| name.trim().eq_ignore_ascii_case("Proxy-Authenticate"))
+                .map(|(_, value)| value.trim().to_string())
+                .collect()
+        }
+
+        fn status_in(&self, range: std::ops::Range<u16>) -> bool {
+            self.status_code()
+                .is_ok_and(|status_code| range.contains(&status_code))
+        }
+
+        /// The declared body size, or `None` for chunked or unsized responses.
+        pub fn content_length(&self) -> Option<u64> {
+            if self.is_chunked() || self.is_decoded() {
+                return None;
+            }
+            let mut length: u64 = 0;
+            let mut len: DWORD = 8;
+            let mut index: DWORD = 0;
+            let result = unsafe {
+                HttpQueryInfoW(
+                    self.handle.raw,
+                    HTTP_QUERY_CONTENT_LENGTH | HTTP_QUERY_FLAG_NUMBER64,
+                    (&mut length as *mut u64) as *mut winapi::ctypes::c_void,
+                    &mut len as *mut DWORD,
+                    &mut index as *mut DWORD,
+                )
+            };
+            if result == 0 {
+                None
+            } else {
+                Some(length)
+            }
+        }
+
+        /// Looks up a single response header by name.
+        pub fn header(&self, name: &str) -> Option<String> {
+            self.query_info(HTTP_QUERY_CUSTOM, Some(name))
+        }
+
+        /// A response header parsed as `T`, e.g. `header_as::<u32>("X-RateLimit-Remaining")`.
+        /// `None` if the header is missing or its trimmed value doesn't parse.
+        pub fn header_as<T: std::str::FromStr>(&self, name: &str) -> Option<T> {
+            self.header(name)?.trim().parse().ok()
+        }
+
+        /// The URL the response was actually served from, after any redirects
+        /// were followed. Without redirects this is the requested URL.
+        pub fn final_url(&self) -> Option<String> {
+            query_string_option(self.handle.raw, INTERNET_OPTION_URL)
+        }
+
+        /// The protocol the response came over, or `None` if WinINet can't
+        /// tell.
+        pub fn http_version(&self) -> Option<HttpVersion> {
+            // Windows versions without HTTP/2 don't know the option at all.
+            let used = query_dword_option(self.handle.raw, INTERNET_OPTION_HTTP_PROTOCOL_USED);
+            if used.is_some_and(|used| used & HTTP_PROTOCOL_FLAG_HTTP2 != 0) {
+                return Some(HttpVersion::Http2);
+            }
+            match self.query_info(HTTP_QUERY_VERSION, None)?.trim() {
+                "HTTP/1.0" => Some(HttpVersion::Http1_0),
+                "HTTP/1.1" => Some(HttpVersion::Http1_1),
+                "HTTP/2" | "HTTP/2.0" => Some(HttpVersion::Http2),
+                _ => None,
+            }
+        }
+
+        /// Whether the response arrived over TLS. Check this rather than the
+        /// requested URL when a redirect could have dropped to plain http.
+        pub fn is_secure(&self) -> bool {
+            match query_dword_option(self.handle.raw, INTERNET_OPTION_SECURITY_FLAGS) {
+                Some(flags) => flags & SECURITY_FLAG_SECURE != 0,
+                None => self.final_url().is_some_and(|url| is_https(&url)),
+            }
+        }
+
+        /// The negotiated protocol and cipher suite, or `None` for plain
+        /// http or when WinINet can't report them.
+        pub fn tls_info(&self) -> Option<TlsInfo> {
+            let mut info: INTERNET_SECURITY_CONNECTION_INFO = unsafe { std::mem::zeroed() };
+            info.dwSize = std::mem::size_of::<INTERNET_SECURITY_CONNECTION_INFO>() as DWORD;
+            info.cipherInfo.dwVersion = SECPKGCONTEXT_CIPHERINFO_V1;
+            let mut len = info.dwSize;
+            let result = unsafe {
+                InternetQueryOptionW(
+                    self.handle.raw,
+                    INTERNET_OPTION_SECURITY_CONNECTION_INFO,
+                    (&mut info as *mut INTERNET_SECURITY_CONNECTION_INFO)
+                        as *mut winapi::ctypes::c_void,
+                    &mut len,
+                )
+            };
+            if result == 0 || info.fSecure == 0 {
+                return None;
+            }
+            let suite = &info.cipherInfo.szCipherSuite;
+            let end = suite.iter().position(|&c| c == 0).unwrap_or(suite.len());
+            Some(TlsInfo {
+                protocol: protocol_name(info.connectionInfo.dwProtocol),
+                cipher_suite: String::from_utf16_lossy(&suite[..end]),
+                cipher_strength: info.connectionInfo.dwCipherStrength,
+            })
+        }
+
+        /// All response headers, CRLF-separated, including the status line.
+        pub fn raw_headers(&self) -> Option<String> {
+            self.query_info(HTTP_QUERY_RAW_HEADERS_CRLF, None)
+        }
+
+        /// Every response header as a `(name, value)` pair, in the order the
+        /// server sent them. Repeated headers such as `Set-Cookie` each get
+        /// their own item. Values have surrounding whitespace removed.
+        pub fn headers(&self) -> impl Iterator<Item = (String, String)> {
+            let raw = self.raw_headers().unwrap_or_default();
+            let mut headers: Vec<(String, String)> = Vec::new();
+            // The first line is the status line.
+            for line in raw.split("\r\n").skip(1) {
+                if line.starts_with([' ', '\t']) {
+                    // An obsolete folded continuation of the previous value.
+                    if let Some((_, value)) = headers.last_mut() {
+                        value.push(' ');
+                        value.push_str(line.trim_matches([' ', '\t']));
+                    }
+                } else if let Some((name, value)) = line.split_once(':') {
+                    headers.push((
+                        name.to_string(),
+                        value.trim_matches([' ', '\t']).to_string(),
+                    ));
+                }
+            }
+            headers.into_iter()
+        }
+
+        /// Every `Set-Cookie` header the response carried, one string per
+        /// cookie with its attributes (`HttpOnly`, `SameSite` ...) intact.
+        pub fn set_cookies(&self) -> Vec<String> {
+            self.headers()
+                .filter(|(name, _)| name.trim().eq_ignore_ascii_case("Set-Cookie"))
+                .map(|(_, value)| value)
+                .collect()
+        }
+
+        fn query_info(&self, level: DWORD, name: Option<&str>) -> Option<String> {
+            // With HTTP_QUERY_CUSTOM the buffer carries the header name in
+            // and the value out, so the name is rewritten before each try.
+            let name = name.map(WideString::from);
+            let mut buffer = vec![0u16; name.as_ref().map_or(0, |name| name.len()).max(256)];
+            loop {
+                if let Some(name) = &name {
+                    buffer[..name.len()].copy_from_slice(name);
+                }
+                let mut len = (buffer.len() * 2) as DWORD;
+                let mut index: DWORD = 0;
+                let result = unsafe {
+                    HttpQueryInfoW(
+                        self.handle.raw,
+                        level,
+                        buffer.as_mut_ptr() as *mut winapi::ctypes::c_void,
+                        &mut len,
+                        &mut index,
+                    )
+                };
+                if result != 0 {
+                    return Some(String::from_utf16_lossy(&buffer[..len as usize / 2]));
+                }
+                if unsafe { GetLastError() } != ERROR_INSUFFICIENT_BUFFER {
+                    return None;
+                }
+                let required = len as usize / 2 + 1;
+                buffer.resize(required.max(buffer.len()), 0);
+            }
+        }
+    }
+    /// Non-blocking requests on an `INTERNET_FLAG_ASYNC` handle. WinINet
+    /// runs each operation on its own worker threads and reports completion
+    /// through a status callback, which wakes the matching future, so any
+    /// executor can drive them without a thread blocked per request.
+    #[cfg(feature = "async")]
+    pub mod asynchronous {
+        use super::{wide, Handle, HttpError, InternetFlag, OpenType, Response};
+        use std::future::Future;
+        use std::io;
+        use std::pin::Pin;
+        use std::ptr::null;
+        use std::sync::{Arc, Mutex, OnceLock};
+        use std::task::{Context, Poll, Waker};
+        use winapi::shared::basetsd::DWORD_PTR;
+        use winapi::shared::minwindef::{DWORD, LPVOID};
+        use winapi::shared::winerror::ERROR_IO_PENDING;
+        use winapi::um::errhandlingapi::GetLastError;
+        use winapi::um::wininet::*;
+
+        /// Shared between a pending operation and the status callback, which
+        /// gets it back through the handle's context value.
+        #[derive(Default)]
+        struct Shared {
+            state: Mutex<State>,
+        }
+
+        #[derive(Default)]
+        struct State {
+            created: Option<HINTERNET>,
+            complete: Option<(DWORD_PTR, DWORD)>,
+            waker: Option<Waker>,
+            // Kept here so the memory WinINet writes into outlives the call.
+            buffer: Box<[u8]>,
+            read: Box<DWORD>,
+            // A read was started and its completion hasn't been seen, so
+            // WinINet may still write into `buffer`.
+            pending: bool,
+            // Data read into `buffer` but not handed out yet, e.g. because
+            // the future that started the read was dropped.
+            filled: std::ops::Range<usize>,
+        }
+
+        impl State {
+            fn take_filled(&mut self, buf: &mut [u8]) -> usize {
+                let len = self.filled.len().min(buf.len());
+                let start = self.filled.start;
+                buf[..len].copy_from_slice(&self.buffer[start..start + len]);
+                self.filled.start += len;
+                len
+            }
+        }
+
+        // SAFETY: `created` is only a handle value; see `Handle`.
+        unsafe impl Send for State {}
+
+        unsafe extern "system" fn callback(
+            _handle: HINTERNET,
+            context: DWORD_PTR,
+            status: DWORD,
+            info: LPVOID,
+            _info_len: DWORD,
+        ) {
+            if context == 0 {
+                return;
+            }
+            let shared = context as *const Shared;
+            match status {
+                INTERNET_STATUS_HANDLE_CREATED => {
+                    let result = &*(info as *const INTERNET_ASYNC_RESULT);
+                    (*shared).state.lock().unwrap().created = Some(result.dwResult as HINTERNET);
+                }
+                INTERNET_STATUS_REQUEST_COMPLETE => {
+                    let result = &*(info as *const INTERNET_ASYNC_RESULT);
+                    let mut state = (*shared).state.lock().unwrap();
+                    state.complete = Some((result.dwResult, result.dwError));
+                    if let Some(waker) = state.waker.take() {
+                        waker.wake();
+                    }
+                }
+                // The last callback for a handle: drop its reference.
+                INTERNET_STATUS_HANDLE_CLOSING => drop(Arc::from_raw(shared)),
+                _ => {}
+            }
+        }
+
+        /// Resolves when the callback reports `INTERNET_STATUS_REQUEST_COMPLETE`.
+        struct Completion<'a>(&'a Shared);
+
+        impl Future for Completion<'_> {
+            type Output = (DWORD_PTR, DWORD);
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                let mut state = self.0.state.lock().unwrap();
+                match state.complete.take() {
+                    Some(result) => Poll::Ready(result),
+                    None => {
+                        state.waker = Some(cx.waker().clone());
+                        Poll::Pending
+                    }
+                }
+            }
+        }
+
+        #[derive(Debug)]
+        pub struct AsyncInternet {
+            handle: Arc<Handle>,
+        }
+
+        impl AsyncInternet {
+            pub fn open(agent: &str) -> Result<AsyncInternet, HttpError> {
+                let internet = super::Internet::open_with(
+                    agent,
+                    OpenType::Direct,
+                    None,
+                    None,
+                    InternetFlag::Async,
+                )?;
+                let previous =
+                    unsafe { InternetSetStatusCallbackW(internet.handle.raw, Some(callback)) };
+                if previous.map(|f| f as usize) == Some(INTERNET_INVALID_STATUS_CALLBACK) {
+                    return Err(HttpError::last_error());
+                }
+                Ok(AsyncInternet {
+                    handle: internet.handle,
+                })
+            }
+
+            /// Starts a GET of `url`. The future resolves once the response
+            /// headers are in.
+            pub async fn get(
+                &self,
+                url: &str,
+                headers: Option<&str>,
+            ) -> Result<AsyncResponse, HttpError> {
+                let secure = super::is_https(url);
+                if let Some(headers) = headers {
+                    super::check_headers(headers)?;
+                }
+                let headers = headers
+                    .map(|headers| wide(headers, "headers"))
+                    .transpose()?;
+                let url = wide(url, "URL")?;
+                let shared = Arc::new(Shared::default());
+                let context = Arc::into_raw(shared.clone()) as DWORD_PTR;
+                // Held as an integer so the future stays `Send`.
+                let opened = unsafe {
+                    InternetOpenUrlW(
+                        self.handle.raw,
+                        url.as_ptr(),
+                        headers.as_ref().map_or(null(), |headers| headers.as_ptr()),
+                        0xFFFFFFFF,
+                        super::CachePolicy::NoCache.flags()
+                            | if secure { INTERNET_FLAG_SECURE } else { 0 },
+                        context,
+                    )
+                } as DWORD_PTR;
+                let raw = if opened != 0 {
+                    opened as HINTERNET
+                } else if unsafe { GetLastError() } == ERROR_IO_PENDING {
+                    let (result, error) = Completion(&shared).await;
+                    if result == 0 {
+                        // A handle that was created closes through the
+                        // callback; otherwise the context is still ours.
+                        match shared.state.lock().unwrap().created {
+                            Some(created) => unsafe {
+                                InternetCloseHandle(created);
+                            },
+                            None => unsafe { drop(Arc::from_raw(context as *const Shared)) },
+                        }
+                        return Err(HttpError::from_code(error));
+                    }
+                    result as HINTERNET
+                } else {
+                    let error = HttpError::last_error();
+                    unsafe { drop(Arc::from_raw(context as *const Shared)) };
+                    return Err(error);
+                };
+                Ok(AsyncResponse {
+                    response: Response {
+                        handle: Arc::new(Handle::new(raw, Some(self.handle.clone()))?),
+                        decoded: false,
+                        raw: false,
+                        reader: OnceLock::new(),
+                        log: None,
+                    },
+                    shared,
+                })
+            }
+        }
+
+        /// A response whose body is read without blocking. Status and headers
+        /// are already available and are queried through `response()`.
+        pub struct AsyncResponse {
+            response: Response,
+            shared: Arc<Shared>,
+        }
+
+        impl AsyncResponse {
+            /// The underlying response, for `status`, `header` and the other
+            /// header queries. Its blocking readers must not be used.
+            pub fn response(&self) -> &Response {
+                &self.response
+            }
+
+            pub fn status(&self) -> u16 {
+                self.response.status() as u16
+            }
+
+            /// Reads the next piece of the body into `buf`; `Ok(0)` is the end.
+            /// Dropping the future mid-read is fine: the next call waits for
+            /// that read and hands out its data first.
+            pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if buf.is_empty() {
+                    return Ok(0);
+                }
+                if self.shared.state.lock().unwrap().pending {
+                    self.finish_read().await?;
+                }
+                let result = {
+                    let mut state = self.shared.state.lock().unwrap();
+                    if !state.filled.is_empty() {
+                        return Ok(state.take_filled(buf));
+                    }
+                    // Nothing is in flight, so the buffer may be replaced.
+                    if state.buffer.len() < buf.len() {
+                        state.buffer = vec![0; buf.len()].into_boxed_slice();
+                    }
+                    *state.read = 0;
+                    state.complete = None;
+                    state.pending = true;
+                    let buffer = state.buffer.as_mut_ptr() as LPVOID;
+                    let read = &mut *state.read as *mut DWORD;
+                    // The callback needs the lock, so release it before the call.
+                    drop(state);
+                    unsafe {
+                        InternetReadFile(
+                            self.response.handle.raw,
+                            buffer,
+                            buf.len().min(DWORD::MAX as usize) as DWORD,
+                            read,
+                        )
+                    }
+                };
+                if result == 0 {
+                    if unsafe { GetLastError() } != ERROR_IO_PENDING {
+                        self.shared.state.lock().unwrap().pending = false;
+                        return Err(io::Error::last_os_error());
+                    }
+                    self.finish_read().await?;
+                } else {
+                    let mut state = self.shared.state.lock().unwrap();
+                    state.pending = false;
+                    state.filled = 0..*state.read as usize;
+                }
+                Ok(self.shared.state.lock().unwrap().take_filled(buf))
+            }
+
+            // Waits for the read in flight and keeps what it brought.
+            async fn finish_read(&self) -> io::Result<()> {
+                let (result, error) = Completion(&self.shared).await;
+                let mut state = self.shared.state.lock().unwrap();
+                state.pending = false;
+                if result == 0 {
+                    return Err(io::Error::other(HttpError::from_code(error)));
+                }
+                state.filled = 0..*state.read as usize;
+                Ok(())
+            }
+
+            pub async fn read_to_vec(&mut self) -> io::Result<Vec<u8>> {
+                let mut body = Vec::new();
+                let mut buffer = vec![0; super::BUFFER_SIZE];
+                loop {
+                    match self.read(&mut buffer).await? {
+                        0 => return Ok(body),
+                        len => body.extend_from_slice(&buffer[..len]),
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Tests of the platform-neutral helpers, which run everywhere.
+#[cfg(test)]
+mod portable_tests {
+    use super::base64;
+    use super::chunked;
+    use super::multipart::Multipart;
+    use super::uri;
+    use std::io::Read;
+
+    #[test]
+    fn encode_uri_component() {
+        assert_eq!(uri::encode_uri_component("AZaz09-._~"), "AZaz09-._~");
+        assert_eq!(
+            uri::encode_uri_component("a b&c=d/e?f+"),
+            "a%20b%26c%3Dd%2Fe%3Ff%2B"
+        );
+        assert_eq!(uri::encode_uri_component("日本"), "%E6%97%A5%E6%9C%AC");
+    }
+
+    #[test]
+    fn query_builder() {
+        let query = uri::QueryBuilder::new()
+            .pair("a", "1")
+            .pair("b", "two words")
+            .build();
+        assert_eq!(query, "a=1&b=two%20words");
+    }
+
+    #[test]
+    fn multipart_framing() {
+        let form = Multipart::new().add_text("field", "value").add_file(
+            "file",
+            "a.txt",
+            "text/plain",
+            b"hello",
+        );
+        let boundary = form.boundary().to_string();
+        let length = form.content_length();
+        let body = String::from_utf8(form.into_body()).unwrap();
+        assert_eq!(body.len() as u64, length);
+        assert_eq!(
+            body,
+            format!(
+                "--{0}\r\nContent-Disposition: form-data; name=\"field\"\r\n\r\nvalue\r\n\
+                 --{0}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+                 Content-Type: text/plain\r\n\r\nhello\r\n--{0}--\r\n",
+                boundary
+            )
+        );
+    }
+
+    #[test]
+    fn chunked_decoder() {
+        let raw = b"4;name=value\r\nWiki\r\n5\r\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n0\r\nExpires: never\r\n\r\n";
+        let mut decoder = chunked::Decoder::new(&raw[..]);
+        let mut body = String::new();
+        decoder.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "Wikipedia in\r\n\r\nchunks.");
+        assert!(decoder.is_done());
+        assert_eq!(
+            decoder.trailers(),
+            &[("Expires".to_string(), "never".to_string())]
+        );
+        let mut decoder = chunked::Decoder::new(&b"5\r\nabc"[..]);
+        let error = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+        let mut decoder = chunked::Decoder::new(&b"zz\r\n"[..]);
+        let error = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn chunked_plain_bodies_pass_through() {
+        let read_all = |body: &[u8], raw_data: bool| {
+            let framed = chunked::is_framed(raw_data, Some("chunked"));
+            let mut decoded = Vec::new();
+            chunked::Reader::new(body, framed)
+                .read_to_end(&mut decoded)
+                .map(|_| decoded)
+        };
+        // WinINet has removed the framing unless RAW_DATA was asked for, so
+        // bodies that merely look like chunk sizes come through as they are.
+        for plain in [
+            &b"0\n"[..],
+            b"0 apples\n",
+            b"1a2b3c4d5e6f7a8b\nrest of a plain body",
+            b"3\r\nabc\r\n0\r\n\r\n",
+            b"",
+        ] {
+            assert_eq!(read_all(plain, false).unwrap(), plain);
+        }
+        assert_eq!(read_all(b"3\r\nabc\r\n0\r\n\r\n", true).unwrap(), b"abc");
+        assert!(!chunked::is_framed(true, None));
+        assert!(!chunked::is_framed(true, Some("gzip")));
+        assert!(chunked::is_framed(true, Some("gzip, Chunked")));
+    }
+
+    #[test]
+    fn chunked_trailers_over_socket() {
+        use std::io::Write;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // Split so the size line and the trailers arrive in pieces.
+            for piece in [
+                &b"5"[..],
+                b";ext=1\r\nhel",
+                b"lo\r\n7\r\n, world\r\n0\r\nX-Check",
+                b"sum: abc\r\nExpires: never\r\n\r\n",
+            ] {
+                stream.write_all(piece).unwrap();
+                stream.flush().unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(20));
+            }
+        });
+        let stream = std::net::TcpStream::connect(address).unwrap();
+        let mut reader = chunked::Reader::new(stream, true);
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello, world");
+        assert_eq!(
+            reader.trailers(),
+            &[
+                ("X-Checksum".to_string(), "abc".to_string()),
+                ("Expires".to_string(), "never".to_string())
+            ]
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn base64_encode() {
+        assert_eq!(base64::encode(b""), "");
+        assert_eq!(base64::encode(b"f"), "Zg==");
+        assert_eq!(base64::encode(b"fo"), "Zm8=");
+        assert_eq!(base64::encode(b"foo"), "Zm9v");
+        assert_eq!(base64::encode(b"foobar"), "Zm9vYmFy");
+    }
+}
+
+#[cfg(all(test, windows))]
+mod tests {
+    use super::http_date;
+    use super::multipart::Multipart;
+    use super::uri;
+    use super::wide_string::WideString;
+    use super::wininet;
+    use std::convert::TryFrom;
+    use std::io::Read;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn it_works() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.get("http://example.com/", None).unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(String::from_utf8(response.body())
+            .unwrap()
+            .find("<h1>Example Domain</h1>")
+            .is_some());
+    }
+
+    #[test]
+    fn post_sends_body() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .post(
+                "http://httpbin.org/post",
+                Some("Content-Type: text/plain\r\n"),
+                b"thin-http",
+            )
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(String::from_utf8(response.body())
+            .unwrap()
+            .find("\"data\": \"thin-http\"")
+            .is_some());
+    }
+
+    #[test]
+    fn post_empty_body() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.post("http://httpbin.org/post", None, &[]).unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(String::from_utf8(response.body())
+            .unwrap()
+            .find("\"Content-Length\": \"0\"")
+            .is_some());
+    }
+
+    #[test]
+    fn request_with_custom_verb() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .request("DELETE", "http://httpbin.org/delete", None, None)
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let response = internet
+            .request("PUT", "http://httpbin.org/put", None, Some(b"thin-http"))
+            .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[test]
+    fn response_implements_read() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let mut response = internet.get("http://example.com/", None).unwrap();
+        let mut body = String::new();
+        response.read_to_string(&mut body).unwrap();
+        assert!(body.find("<h1>Example Domain</h1>").is_some());
+    }
+
+    #[test]
+    fn bytes_yields_exact_length() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.get("http://httpbin.org/bytes/2500", None).unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.as_bytes().count(), 2500);
+    }
+
+    #[test]
+    fn error_carries_win32_code() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let error = internet
+            .get("http://nonexistent.invalid/", None)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.code(),
+            winapi::um::wininet::ERROR_INTERNET_NAME_NOT_RESOLVED
+        );
+        assert!(!error.message().is_empty());
+    }
+
+    #[test]
+    fn response_headers() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.get("http://example.com/", None).unwrap();
+        assert!(response
+            .header("Content-Type")
+            .unwrap()
+            .starts_with("text/html"));
+        assert!(response.header("X-Not-Present").is_none());
+        assert!(response.raw_headers().unwrap().starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn content_length() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.get("http://httpbin.org/bytes/2500", None).unwrap();
+        assert_eq!(response.content_length(), Some(2500));
+        let response = internet
+            .get("http://httpbin.org/stream-bytes/2500", None)
+            .unwrap();
+        assert_eq!(response.content_length(), None);
+    }
+
+    #[test]
+    fn read_to_vec() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .get("http://httpbin.org/bytes/40000", None)
+            .unwrap();
+        assert_eq!(response.read_to_vec().unwrap().len(), 40000);
+    }
+
+    #[test]
+    fn read_to_vec_bogus_content_length() {
+        use std::io::Write;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 4096];
+            let _ = stream.read(&mut request).unwrap();
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 18446744073709551615\r\n\
+                      Connection: close\r\n\r\nshort",
+                )
+                .unwrap();
+        });
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let url = format!("http://127.0.0.1:{}/", port);
+        // Fails as truncated instead of trying to allocate the claimed size.
+        let result = internet
+            .get(&url, None)
+            .and_then(|response| response.read_to_vec().map_err(wininet::HttpError::from));
+        assert!(result.is_err());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn text_decodes_body() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.get("http://example.com/", None).unwrap();
+        assert!(response.text().unwrap().contains("<h1>Example Domain</h1>"));
+    }
+
+    #[test]
+    fn connect_timeout() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        internet
+            .set_connect_timeout(Duration::from_millis(500))
+            .unwrap();
+        let start = Instant::now();
+        // 10.255.255.1 is a non-routable address, so the connect never completes.
+        assert!(internet.get("http://10.255.255.1/", None).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn get_no_redirect() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .get_no_redirect("http://httpbin.org/redirect-to?url=/get", None)
+            .unwrap();
+        assert_eq!(response.status(), 302);
+        assert_eq!(response.header("Location").unwrap(), "/get");
+    }
+
+    #[test]
+    fn bytes_with_small_capacity() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.get("http://httpbin.org/bytes/2500", None).unwrap();
+        assert_eq!(response.bytes_with_capacity(7).count(), 2500);
+    }
+
+    #[test]
+    fn accept_invalid_certs() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        assert!(internet
+            .request_builder("GET", "https://self-signed.badssl.com/")
+            .send()
+            .is_err());
+        let response = internet
+            .request_builder("GET", "https://self-signed.badssl.com/")
+            .danger_accept_invalid_certs(true)
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[test]
+    fn basic_auth_header() {
+        assert_eq!(
+            wininet::basic_auth_header("Aladdin", Some("open sesame")),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+        assert_eq!(wininet::basic_auth_header("user", None), "Basic dXNlcjo=");
+        assert_eq!(
+            wininet::basic_auth_header("ユーザ", Some("")),
+            "Basic 44Om44O844K2Og=="
+        );
+    }
+
+    #[test]
+    fn basic_auth() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .request_builder("GET", "http://httpbin.org/basic-auth/user/passwd")
+            .basic_auth("user", Some("passwd"))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[test]
+    fn proxy_credentials() {
+        use winapi::um::wininet::{INTERNET_OPTION_PROXY_PASSWORD, INTERNET_OPTION_PROXY_USERNAME};
+
+        let internet = wininet::Internet::open("agent", Some("proxy.example:3128")).unwrap();
+        internet.set_proxy_credentials("user", "passwd").unwrap();
+        let option = |option| wininet::query_string_option(internet.as_raw_handle(), option);
+        assert_eq!(
+            option(INTERNET_OPTION_PROXY_USERNAME).as_deref(),
+            Some("user")
+        );
+        assert_eq!(
+            option(INTERNET_OPTION_PROXY_PASSWORD).as_deref(),
+            Some("passwd")
+        );
+    }
+
+    #[test]
+    fn cookies() {
+        wininet::set_cookie("http://httpbin.org/", "thin=http").unwrap();
+        assert!(wininet::get_cookie("http://httpbin.org/")
+            .unwrap()
+            .contains("thin=http"));
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.get("http://httpbin.org/cookies", None).unwrap();
+        assert!(response.text().unwrap().contains("\"thin\": \"http\""));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn decoded_body() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.get("http://httpbin.org/gzip", None).unwrap();
+        let body = String::from_utf8(response.decoded_body().unwrap()).unwrap();
+        assert!(body.contains("\"gzipped\": true"));
+        let response = internet.get("http://httpbin.org/deflate", None).unwrap();
+        let body = String::from_utf8(response.decoded_body().unwrap()).unwrap();
+        assert!(body.contains("\"deflated\": true"));
+    }
+
+    #[test]
+    fn request_builder() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .request_builder("POST", "http://httpbin.org/post")
+            .header("X-Thin", "first")
+            .header("x-thin", "second")
+            .header("Content-Type", "text/plain")
+            .body(b"thin-http")
+            .timeout(Duration::from_secs(30))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body = response.text().unwrap();
+        assert!(body.contains("\"X-Thin\": \"second\""));
+        assert!(body.contains("\"data\": \"thin-http\""));
+
+        let response = internet
+            .request_builder("GET", "http://httpbin.org/redirect-to?url=/get")
+            .no_redirect()
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 302);
+    }
+
+    #[test]
+    fn status_predicates() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.get("http://httpbin.org/status/404", None).unwrap();
+        assert_eq!(response.status_code().unwrap(), 404);
+        assert!(response.is_client_error());
+        assert!(!response.is_success());
+        assert_eq!(response.status_text().unwrap(), "NOT FOUND");
+        let response = internet.get("http://httpbin.org/status/503", None).unwrap();
+        assert!(response.is_server_error());
+    }
+
+    #[test]
+    fn body_from_reader() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let body = vec![b'x'; 100_000];
+        let response = internet
+            .request_builder("POST", "http://httpbin.org/post")
+            .body_from_reader(&body[..], Some(body.len() as u64))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(response
+            .text()
+            .unwrap()
+            .contains("\"Content-Length\": \"100000\""));
+    }
+
+    #[test]
+    fn save_to() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .get("http://httpbin.org/bytes/40000", None)
+            .unwrap();
+        let path = std::env::temp_dir().join("thin-http-save-to.bin");
+        assert_eq!(response.save_to(&path).unwrap(), 40000);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 40000);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn save_to_with_progress() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .get("http://httpbin.org/bytes/40000", None)
+            .unwrap();
+        let path = std::env::temp_dir().join("thin-http-progress.bin");
+        let mut last = (0, None);
+        response
+            .save_to_with_progress(&path, |downloaded, total| last = (downloaded, total))
+            .unwrap();
+        assert_eq!(last, (40000, Some(40000)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn range_request() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .request_builder("GET", "http://httpbin.org/range/1000")
+            .range(100, Some(199))
+            .send()
+            .unwrap();
+        assert!(response.is_partial_content());
+        assert_eq!(
+            response.content_range(),
+            Some(wininet::ContentRange {
+                start: 100,
+                end: 199,
+                total: Some(1000)
+            })
+        );
+        assert_eq!(response.read_to_vec().unwrap().len(), 100);
+    }
+
+    #[test]
+    fn resume_download() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let path = std::env::temp_dir().join("thin-http-resume.bin");
+        std::fs::write(&path, vec![b'a'; 300]).unwrap();
+        assert_eq!(
+            internet
+                .resume_download("http://httpbin.org/range/1000", &path)
+                .unwrap(),
+            1000
+        );
+        let body = std::fs::read(&path).unwrap();
+        assert_eq!(body[300], b'a' + (300 % 26) as u8);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn form() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .request_builder("POST", "http://httpbin.org/post")
+            .form(&[("name", "a&b=c d"), ("empty", "")])
+            .send()
+            .unwrap();
+        let body = response.text().unwrap();
+        assert!(body.contains("\"name\": \"a&b=c d\""));
+        assert!(body.contains("\"empty\": \"\""));
+    }
+
+    #[test]
+    fn multipart() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let form = Multipart::new().add_text("field", "value").add_file(
+            "file",
+            "a.txt",
+            "text/plain",
+            b"hello",
+        );
+        let response = internet
+            .request_builder("POST", "http://httpbin.org/post")
+            .multipart(form)
+            .send()
+            .unwrap();
+        let body = response.text().unwrap();
+        assert!(body.contains("\"field\": \"value\""));
+        assert!(body.contains("\"file\": \"hello\""));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json() {
+        #[derive(serde::Serialize)]
+        struct Request {
+            name: &'static str,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct Echo {
+            json: std::collections::HashMap<String, String>,
+        }
+
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .request_builder("POST", "http://httpbin.org/post")
+            .json(&Request { name: "thin-http" })
+            .send()
+            .unwrap();
+        let echo: Echo = response.json().unwrap();
+        assert_eq!(echo.json["name"], "thin-http");
+    }
+
+    #[test]
+    fn connection_reuse() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let connection = internet.connect("httpbin.org", 80).unwrap();
+        let first = connection.request("GET", "/get", None, None).unwrap();
+        drop(internet);
+        let second = connection
+            .request_builder("POST", "/post")
+            .body(b"thin-http")
+            .send()
+            .unwrap();
+        assert_eq!(first.status(), 200);
+        assert_eq!(second.status(), 200);
+    }
+
+    #[test]
+    fn connect_to_explicit_port() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let connection = internet
+            .connect_with(wininet::Scheme::Http, "portquiz.net", Some(8080))
+            .unwrap();
+        let response = connection.request("GET", "/", None, None).unwrap();
+        assert_eq!(response.status(), 200);
+        let connection = internet.connect_url("http://portquiz.net:8080/").unwrap();
+        let response = connection.request("GET", "/", None, None).unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[test]
+    fn move_across_threads() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let status = std::thread::spawn(move || {
+            let response = internet.get("http://example.com/", None).unwrap();
+            std::thread::spawn(move || response.status())
+                .join()
+                .unwrap()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(status, 200);
+    }
+
+    #[test]
+    fn cancel() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .get("http://httpbin.org/drip?duration=20&numbytes=20", None)
+            .unwrap();
+        let start = Instant::now();
+        let error = std::thread::scope(|scope| {
+            let reader = scope.spawn(|| response.read_to_vec());
+            std::thread::sleep(Duration::from_secs(1));
+            response.cancel();
+            reader.join().unwrap().unwrap_err()
+        });
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(matches!(
+            wininet::HttpError::from(error),
+            wininet::HttpError::Cancelled
+        ));
+    }
+
+    #[test]
+    fn head() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let mut response = internet.head("http://httpbin.org/bytes/100", None).unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.content_length(), Some(100));
+        assert!(response.header("Content-Type").is_some());
+        assert_eq!(response.as_bytes().count(), 0);
+        let mut buffer = [0u8; 16];
+        assert_eq!(response.read(&mut buffer).unwrap(), 0);
+    }
+
+    #[test]
+    fn final_url() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .get("http://httpbin.org/redirect-to?url=/get", None)
+            .unwrap();
+        assert_eq!(response.final_url().unwrap(), "http://httpbin.org/get");
+        let response = internet.get("http://httpbin.org/get", None).unwrap();
+        assert_eq!(response.final_url().unwrap(), "http://httpbin.org/get");
+    }
+
+    #[test]
+    fn wide_string_round_trip() {
+        let wide = WideString::from("日本語 text");
+        assert_eq!(wide.last(), Some(&0));
+        assert_eq!(wide.to_string_lossy(), "日本語 text");
+        let buffer = [0x61, 0x62, 0, 0x63, 0];
+        let wide = WideString::try_from(&buffer[..]).unwrap();
+        assert_eq!(wide.to_string_lossy(), "ab");
+        assert!(WideString::try_from(&buffer[..2]).is_err());
+    }
+
+    #[test]
+    fn interior_nul_is_refused() {
+        assert_eq!(WideString::try_new("a\0b").unwrap_err().position, 1);
+        assert!(WideString::try_new("ab").is_ok());
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let error = internet
+            .get("http://httpbin.org/get", Some("X-Foo: bar\0\r\nX-Evil: 1"))
+            .err()
+            .unwrap();
+        assert!(matches!(error, wininet::HttpError::InvalidInput(_)));
+        let invalid = |result: Result<(), wininet::HttpError>| {
+            matches!(result, Err(wininet::HttpError::InvalidInput(_)))
+        };
+        assert!(invalid(wininet::Internet::open("age\0nt", None).map(drop)));
+        assert!(invalid(
+            wininet::Internet::open("agent", Some("proxy\0:8080")).map(drop)
+        ));
+        assert!(invalid(
+            internet.set_proxy_credentials("user\0admin", "pass")
+        ));
+        assert!(invalid(wininet::set_cookie(
+            "http://httpbin.org/",
+            "a=b\0; c=d"
+        )));
+        assert_eq!(wininet::get_cookie("http://httpbin.org/\0"), None);
+    }
+
+    #[test]
+    fn header_injection_is_refused() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let error = internet
+            .request_builder("GET", "http://httpbin.org/get")
+            .header("X-Foo", "bar\r\nX-Injected: evil")
+            .send()
+            .err()
+            .unwrap();
+        assert!(matches!(error, wininet::HttpError::InvalidInput(_)));
+        let error = internet
+            .get(
+                "http://httpbin.org/get",
+                Some("X-Foo: bar\nX-Injected: evil"),
+            )
+            .err()
+            .unwrap();
+        assert!(matches!(error, wininet::HttpError::InvalidInput(_)));
+        let response = internet
+            .get(
+                "http://httpbin.org/get",
+                Some("X-Foo: bar\r\nX-Bar: baz\r\n"),
+            )
+            .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[test]
+    fn open_offline() {
+        let internet = wininet::Internet::open_offline("agent").unwrap();
+        assert!(internet
+            .get("http://httpbin.org/uuid?offline-test", None)
+            .and_then(|response| response.read_to_vec().map_err(Into::into))
+            .is_err());
+    }
+
+    #[test]
+    fn open_with_system_proxy() {
+        let internet = wininet::Internet::open_with_system_proxy("agent").unwrap();
+        let response = internet.get("http://example.com/", None).unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(wininet::Internet::open_with_system_proxy_no_autoproxy("agent").is_ok());
+    }
+
+    #[test]
+    fn retry() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        // Nothing listens on port 9, so every attempt is refused.
+        let start = Instant::now();
+        let error = internet
+            .request_builder("GET", "http://127.0.0.1:9/")
+            .retry(2, Duration::from_millis(200))
+            .send()
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.code(),
+            winapi::um::wininet::ERROR_INTERNET_CANNOT_CONNECT
+        );
+        assert!(start.elapsed() >= Duration::from_millis(600));
+
+        let start = Instant::now();
+        internet
+            .request_builder("POST", "http://127.0.0.1:9/")
+            .retry(2, Duration::from_millis(500))
+            .send()
+            .err()
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        let response = internet
+            .request_builder("GET", "http://httpbin.org/status/404")
+            .retry(2, Duration::from_secs(5))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[test]
+    fn headers() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .get_no_redirect(
+                "http://httpbin.org/response-headers?Set-Cookie=a%3D1&Set-Cookie=b%3D2",
+                None,
+            )
+            .unwrap();
+        let headers: Vec<_> = response.headers().collect();
+        let cookies: Vec<_> = headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Set-Cookie"))
+            .map(|(_, value)| value.as_str())
+            .collect();
+        assert_eq!(cookies, ["a=1", "b=2"]);
+        assert!(headers
+            .iter()
+            .any(|(name, value)| name.eq_ignore_ascii_case("Content-Type")
+                && value == "application/json"));
+    }
+
+    #[test]
+    fn content_type_parsing() {
+        let parsed: wininet::ContentType = "Text/HTML; Charset=\"UTF-8\"".parse().unwrap();
+        assert_eq!(parsed.media_type, "text/html");
+        assert_eq!(parsed.charset.as_deref(), Some("utf-8"));
+        let parsed: wininet::ContentType =
+            "multipart/form-data; boundary=\"a;b\"; charset=shift_jis"
+                .parse()
+                .unwrap();
+        assert_eq!(parsed.charset.as_deref(), Some("shift_jis"));
+        let parsed: wininet::ContentType = "application/json".parse().unwrap();
+        assert_eq!(parsed.charset, None);
+        assert!("nonsense".parse::<wininet::ContentType>().is_err());
+    }
+
+    #[test]
+    fn per_request_user_agent() {
+        let internet = wininet::Internet::open("default-agent", None).unwrap();
+        let body = internet
+            .request_builder("GET", "http://httpbin.org/user-agent")
+            .user_agent("legacy-agent/1.0")
+            .send()
+            .unwrap()
+            .text()
+            .unwrap();
+        assert!(body.contains("legacy-agent/1.0"));
+        let body = internet
+            .get("http://httpbin.org/user-agent", None)
+            .unwrap()
+            .text()
+            .unwrap();
+        assert!(body.contains("default-agent"));
+    }
+
+    #[test]
+    fn lines() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let mut lines = internet
+            .get("http://httpbin.org/stream/5", None)
+            .unwrap()
+            .lines();
+        let first = lines.next().unwrap().unwrap();
+        assert!(first.starts_with('{'));
+        assert_eq!(lines.count(), 4);
+    }
+
+    #[test]
+    fn lines_arrive_incrementally() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        // Drips one byte a second; the first must not wait for the rest.
+        let mut response = internet
+            .get(
+                "http://httpbin.org/drip?duration=5&numbytes=5&delay=0",
+                None,
+            )
+            .unwrap();
+        let start = Instant::now();
+        let mut buffer = [0u8; 1024];
+        assert!(response.read(&mut buffer).unwrap() > 0);
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn send_with_deadline() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let start = Instant::now();
+        let result = internet
+            .request_builder("GET", "http://httpbin.org/drip?duration=20&numbytes=20")
+            .send_with_deadline(start + Duration::from_secs(3))
+            .and_then(|response| response.read_to_vec().map_err(Into::into));
+        assert_eq!(
+            result.err().unwrap().code(),
+            winapi::um::wininet::ERROR_INTERNET_TIMEOUT
+        );
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn extra_flags() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .request_builder("GET", "http://httpbin.org/redirect/1")
+            .extra_flags(winapi::um::wininet::INTERNET_FLAG_NO_AUTO_REDIRECT)
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 302);
+    }
+
+    #[test]
+    fn cache_policy() {
+        let mut internet = wininet::Internet::open("agent", None).unwrap();
+        let url = "http://httpbin.org/cache/600";
+        internet.set_cache_policy(wininet::CachePolicy::ReloadFromServer);
+        let first = internet.get(url, None).unwrap().read_to_vec().unwrap();
+        let cached = internet
+            .request_builder("GET", url)
+            .cache_policy(wininet::CachePolicy::CacheOnly)
+            .send()
+            .unwrap()
+            .read_to_vec()
+            .unwrap();
+        assert_eq!(first, cached);
+        assert!(internet
+            .request_builder("GET", "http://httpbin.org/uuid?never-cached")
+            .cache_policy(wininet::CachePolicy::CacheOnly)
+            .send()
+            .and_then(|response| response.read_to_vec().map_err(Into::into))
+            .is_err());
+    }
+
+    #[test]
+    fn http_date_format() {
+        let time = std::time::UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(http_date::format(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        let time = std::time::UNIX_EPOCH + Duration::from_secs(951782400);
+        assert_eq!(http_date::format(time), "Tue, 29 Feb 2000 00:00:00 GMT");
+    }
+
+    #[test]
+    fn conditional_get() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let url = "http://httpbin.org/etag/abc";
+        let response = internet.get(url, None).unwrap();
+        assert!(!response.is_not_modified());
+        let response = internet
+            .request_builder("GET", url)
+            .if_none_match("\"abc\"")
+            .send()
+            .unwrap();
+        assert!(response.is_not_modified());
+        let response = internet
+            .request_builder("GET", "http://httpbin.org/cache")
+            .if_modified_since(std::time::SystemTime::now())
+            .send()
+            .unwrap();
+        assert!(response.is_not_modified());
+    }
+
+    #[test]
+    fn http_date_parse() {
+        let expected = std::time::UNIX_EPOCH + Duration::from_secs(784111777);
+        for date in &[
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ] {
+            assert_eq!(http_date::parse(date), Some(expected));
+        }
+        assert_eq!(http_date::parse("06 Nov 1994"), None);
+        assert_eq!(http_date::parse("Sun, 06 Nov 1994 24:00:00 GMT"), None);
+    }
+
+    #[test]
+    fn last_modified_and_etag() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .get(
+                "http://httpbin.org/response-headers?Last-Modified=Sun,%2006%20Nov%201994%2008:49:37%20GMT&ETag=%22xyz%22",
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            response.last_modified(),
+            Some(std::time::UNIX_EPOCH + Duration::from_secs(784111777))
+        );
+        assert_eq!(response.etag().as_deref(), Some("\"xyz\""));
+    }
+
+    #[test]
+    fn set_cookies() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .get_no_redirect(
+                "http://httpbin.org/response-headers?Set-Cookie=a%3D1%3B%20HttpOnly&Set-Cookie=b%3D2%3B%20SameSite%3DLax",
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            response.set_cookies(),
+            ["a=1; HttpOnly", "b=2; SameSite=Lax"]
+        );
+    }
+
+    #[test]
+    fn auto_decompress() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .request_builder("GET", "http://httpbin.org/gzip")
+            .auto_decompress(true)
+            .send()
+            .unwrap();
+        assert!(response.is_decoded());
+        assert!(response.text().unwrap().contains("\"gzipped\": true"));
+        let response = internet
+            .request_builder("GET", "http://httpbin.org/bytes/64?seed=1")
+            .auto_decompress(true)
+            .send()
+            .unwrap();
+        assert!(!response.is_decoded());
+        assert_eq!(response.content_length(), Some(64));
+        assert_eq!(response.read_to_vec().unwrap().len(), 64);
+    }
+
+    #[test]
+    fn one_shot_get() {
+        let response = wininet::get("http://httpbin.org/user-agent").unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(response.text().unwrap().contains("thin-http/"));
+        assert!(wininet::set_default_agent("late/1.0").is_err());
+        let first = wininet::default_internet().unwrap() as *const wininet::Internet;
+        let second = wininet::default_internet().unwrap() as *const wininet::Internet;
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn response_outlives_its_handles() {
+        let response = {
+            let internet = wininet::Internet::open("agent", None).unwrap();
+            let connection = internet.connect("httpbin.org", 80).unwrap();
+            connection
+                .request("GET", "/bytes/2048", None, None)
+                .unwrap()
+        };
+        assert_eq!(response.read_to_vec().unwrap().len(), 2048);
+    }
+
+    #[test]
+    fn tls_info() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.get("https://example.com/", None).unwrap();
+        assert!(response.is_secure());
+        let info = response.tls_info().unwrap();
+        assert!(info.protocol.starts_with("TLS"));
+        let response = internet.get("http://example.com/", None).unwrap();
+        assert!(!response.is_secure());
+        assert!(response.tls_info().is_none());
+    }
+
+    #[test]
+    fn min_tls_version() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        internet
+            .set_min_tls_version(wininet::TlsVersion::Tls1_2)
+            .unwrap();
+        // This badssl.com endpoint only speaks TLS 1.0.
+        assert!(internet
+            .get("https://tls-v1-0.badssl.com:1010/", None)
+            .is_err());
+        assert_eq!(
+            internet.get("https://example.com/", None).unwrap().status(),
+            200
+        );
+    }
+
+    #[test]
+    fn client_cert_lookup() {
+        assert!(matches!(
+            wininet::ClientCert::from_thumbprint("not hex"),
+            Err(wininet::HttpError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            wininet::ClientCert::from_thumbprint(
+                "00:00:00:00:00:00:00:00:00:00:00:00:00:00:00:00:00:00:00:00"
+            ),
+            Err(wininet::HttpError::Win32 { .. })
+        ));
+    }
+
+    #[test]
+    fn error_for_status() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.get("http://httpbin.org/get", None).unwrap();
+        assert!(response.error_for_status().is_ok());
+        let error = internet
+            .get("http://httpbin.org/status/418", None)
+            .unwrap()
+            .error_for_status()
+            .err()
+            .unwrap();
+        assert_eq!(error.status(), Some(418));
+        assert!(error.to_string().contains("teapot"));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_get() {
+        use std::future::Future;
+        use std::sync::Arc;
+        use std::task::{Context, Poll, Wake};
+
+        struct Unpark(std::thread::Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        fn block_on<F: Future>(future: F) -> F::Output {
+            let waker = Arc::new(Unpark(std::thread::current())).into();
+            let mut context = Context::from_waker(&waker);
+            let mut future = Box::pin(future);
+            loop {
+                match future.as_mut().poll(&mut context) {
+                    Poll::Ready(output) => return output,
+                    Poll::Pending => std::thread::park(),
+                }
+            }
+        }
+
+        fn assert_send<T: Send>(_: &T) {}
+
+        let internet = wininet::asynchronous::AsyncInternet::open("agent").unwrap();
+        let future = async {
+            let mut response = internet
+                .get("http://httpbin.org/bytes/40000", None)
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 200);
+            response.read_to_vec().await.unwrap()
+        };
+        assert_send(&future);
+        assert_eq!(block_on(future).len(), 40000);
+    }
+
+    #[test]
+    fn pool() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let pool = internet.pool(Duration::from_millis(500));
+        for path in &["/get", "/uuid"] {
+            let url = format!("http://httpbin.org{}", path);
+            assert_eq!(pool.get(&url).send().unwrap().status(), 200);
+        }
+        assert_eq!(
+            pool.request_builder("GET", "https://httpbin.org/get")
+                .send()
+                .unwrap()
+                .status(),
+            200
+        );
+        assert_eq!(pool.len(), 2);
+        std::thread::sleep(Duration::from_secs(1));
+        pool.get("http://example.com/").send().unwrap();
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn bytes_read() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.get("http://httpbin.org/bytes/3000", None).unwrap();
+        let mut bytes = response.as_bytes();
+        assert_eq!(bytes.bytes_read(), 0);
+        bytes.by_ref().take(100).for_each(drop);
+        assert_eq!(bytes.bytes_read(), 100);
+        bytes.by_ref().for_each(drop);
+        assert_eq!(bytes.bytes_read(), 3000);
+    }
+
+    #[test]
+    fn chunks() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .get("http://httpbin.org/bytes/50000?seed=7", None)
+            .unwrap();
+        let mut total = 0;
+        for chunk in response.chunks(4096) {
+            let chunk = chunk.unwrap();
+            assert!(!chunk.is_empty() && chunk.len() <= 4096);
+            total += chunk.len();
+        }
+        assert_eq!(total, 50000);
+    }
+
+    #[test]
+    fn read_into() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .get("http://httpbin.org/bytes/10000", None)
+            .unwrap();
+        let mut buffer = [0u8; 1000];
+        let mut total = 0;
+        loop {
+            match response.read_into(&mut buffer).unwrap() {
+                0 => break,
+                read_size => total += read_size,
+            }
+        }
+        assert_eq!(total, 10000);
+        assert_eq!(response.read_into(&mut buffer).unwrap(), 0);
+    }
+
+    #[test]
+    fn connect_to_with_host_header() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let address = std::net::ToSocketAddrs::to_socket_addrs(&("httpbin.org", 80))
+            .unwrap()
+            .find(|address| address.is_ipv4())
+            .unwrap();
+        let body = internet
+            .request_builder("GET", "http://placeholder.invalid/headers")
+            .connect_to(&address.ip().to_string())
+            .host_header("httpbin.org")
+            .send()
+            .unwrap()
+            .text()
+            .unwrap();
+        assert!(body.contains("\"Host\": \"httpbin.org\""));
+    }
+
+    #[test]
+    fn logger() {
+        use std::sync::{Arc, Mutex};
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut internet = wininet::Internet::open("agent", None).unwrap();
+        let sink = events.clone();
+        internet.set_logger(wininet::Logger::new(move |event| {
+            sink.lock().unwrap().push(match event {
+                wininet::LogEvent::Request { method, url, .. } => format!("{} {}", method, url),
+                wininet::LogEvent::Response { status, .. } => format!("status {}", status),
+                wininet::LogEvent::Body { .. } => "body".to_string(),
+                wininet::LogEvent::Complete { bytes, .. } => format!("{} bytes", bytes),
+            })
+        }));
+        internet
+            .get("http://httpbin.org/bytes/100", None)
+            .unwrap()
+            .read_to_vec()
+            .unwrap();
+        internet
+            .request_builder("POST", "http://httpbin.org/status/201")
+            .body(b"secret")
+            .send()
+            .unwrap()
+            .read_to_vec()
+            .unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "GET http://httpbin.org/bytes/100",
+                "status 200",
+                "100 bytes",
+                "POST http://httpbin.org/status/201",
+                "status 201",
+                "0 bytes",
+            ]
+        );
+
+        let logged_headers = |secrets| {
+            let headers = Arc::new(Mutex::new(String::new()));
+            let sink = headers.clone();
+            let mut internet = wininet::Internet::open("agent", None).unwrap();
+            let logger = wininet::Logger::new(move |event| {
+                if let wininet::LogEvent::Request { headers, .. } = event {
+                    sink.lock().unwrap().push_str(headers);
+                }
+            });
+            internet.set_logger(logger.with_secrets(secrets));
+            internet
+                .request_builder("GET", "http://httpbin.org/get")
+                .basic_auth("user", Some("passwd"))
+                .header("Cookie", "session=secret")
+                .header("X-Foo", "bar")
+                .send()
+                .unwrap();
+            internet
+                .get("http://httpbin.org/get", Some("Cookie: session=secret\r\n"))
+                .unwrap();
+            let headers = headers.lock().unwrap().clone();
+            headers
+        };
+        let redacted = logged_headers(false);
+        assert!(
+            redacted.contains("Authorization: <redacted>\r\n"),
+            "{}",
+            redacted
+        );
+        assert!(redacted.contains("Cookie: <redacted>\r\n"), "{}", redacted);
+        assert!(redacted.contains("X-Foo: bar\r\n"), "{}", redacted);
+        assert!(!redacted.contains("secret") && !redacted.contains("Basic "));
+        let revealed = logged_headers(true);
+        assert!(revealed.contains("Authorization: Basic ") && revealed.contains("session=secret"));
+    }
+
+    #[test]
+    fn raw_handle_ownership() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.get("http://httpbin.org/get", None).unwrap();
+        assert!(!response.as_raw_handle().is_null());
+        let internet = internet.into_raw_handle().unwrap_err();
+        drop(response);
+        let raw = internet.into_raw_handle().unwrap();
+        let internet = unsafe { wininet::Internet::from_raw_handle(raw) }.unwrap();
+        assert_eq!(internet.as_raw_handle(), raw);
+        assert_eq!(
+            internet
+                .get("http://httpbin.org/get", None)
+                .unwrap()
+                .status(),
+            200
+        );
+    }
+
+    #[test]
+    fn clear_cookies_for() {
+        let url = "http://httpbin.org/cookies";
+        wininet::set_cookie(url, "cleared=yes").unwrap();
+        assert!(wininet::get_cookie(url).unwrap().contains("cleared=yes"));
+        wininet::clear_cookies_for(url).unwrap();
+        assert!(!wininet::get_cookie(url)
+            .unwrap_or_default()
+            .contains("cleared"));
+    }
+
+    #[test]
+    fn clear_cache() {
+        wininet::clear_cache().unwrap();
+    }
+
+    #[test]
+    fn read_to_vec_limited() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let get = |url| internet.get(url, None).unwrap();
+        let body = get("http://httpbin.org/bytes/100")
+            .read_to_vec_limited(100)
+            .unwrap();
+        assert_eq!(body.len(), 100);
+        let error = get("http://httpbin.org/bytes/100")
+            .read_to_vec_limited(99)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        // Chunked, so the cap is only hit while reading.
+        let error = get("http://httpbin.org/stream-bytes/5000?chunk_size=1000")
+            .read_to_vec_limited(4096)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn keep_alive() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let connection = internet.connect("httpbin.org", 80).unwrap();
+        for keep in [true, true, false] {
+            let response = connection
+                .request_builder("GET", "/get")
+                .keep_alive(keep)
+                .send()
+                .unwrap();
+            assert_eq!(response.status(), 200);
+            response.read_to_vec().unwrap();
+        }
+    }
+
+    #[test]
+    fn no_cookies() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        wininet::set_cookie("http://httpbin.org/", "probe=stored").unwrap();
+        let body = internet
+            .request_builder("GET", "http://httpbin.org/cookies")
+            .no_cookies()
+            .send()
+            .unwrap()
+            .read_to_vec()
+            .unwrap();
+        assert!(!String::from_utf8(body).unwrap().contains("probe"));
+        internet
+            .request_builder(
+                "GET",
+                "http://httpbin.org/response-headers?Set-Cookie=anonymous%3D1",
+            )
+            .no_cookies()
+            .send()
+            .unwrap();
+        let stored = wininet::get_cookie("http://httpbin.org/").unwrap_or_default();
+        assert!(!stored.contains("anonymous"));
+        wininet::clear_cookies_for("http://httpbin.org/").unwrap();
+    }
+
+    #[test]
+    fn body_from_reader_length_mismatch() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let send = |length| {
+            internet
+                .request_builder("POST", "http://httpbin.org/post")
+                .body_from_reader(&b"hello"[..], Some(length))
+                .send()
+        };
+        assert_eq!(send(5).unwrap().status(), 200);
+        for length in [3, 10] {
+            let result = send(length);
+            assert!(matches!(result, Err(wininet::HttpError::InvalidInput(_))));
+        }
+    }
+
+    #[test]
+    fn text_byte_order_marks() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        // httpbin serves the decoded bytes as `text/html; charset=utf-8`.
+        for encoded in ["__5oAGkA", "77u_aGk=", "_v8AaABp"] {
+            let url = format!("http://httpbin.org/base64/{}", encoded);
+            let text = internet.get(&url, None).unwrap().text().unwrap();
+            assert_eq!(text, "hi");
+        }
+    }
+
+    #[test]
+    fn refresh() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let refresh = |value| {
+            let url = format!(
+                "http://httpbin.org/response-headers?Refresh={}",
+                uri::encode_uri_component(value)
+            );
+            internet.get(&url, None).unwrap().refresh()
+        };
+        let target = "http://example.com/next".to_string();
+        assert_eq!(
+            refresh("5; url=http://example.com/next"),
+            Some((Duration::from_secs(5), target.clone()))
+        );
+        assert_eq!(
+            refresh("2.5;URL='http://example.com/next'"),
+            Some((Duration::from_secs(2), target.clone()))
+        );
+        assert_eq!(
+            refresh("http://example.com/next"),
+            Some((Duration::ZERO, target))
+        );
+        assert_eq!(refresh("30"), None);
+    }
+
+    #[test]
+    fn set_option() {
+        use winapi::um::wininet::{
+            INTERNET_OPTION_CONNECT_TIMEOUT, INTERNET_OPTION_RECEIVE_TIMEOUT,
+        };
+
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        internet
+            .set_option_dword(INTERNET_OPTION_CONNECT_TIMEOUT, 5000)
+            .unwrap();
+        unsafe { internet.set_option(INTERNET_OPTION_RECEIVE_TIMEOUT, &5000u32.to_ne_bytes()) }
+            .unwrap();
+        let result = unsafe { internet.set_option(INTERNET_OPTION_RECEIVE_TIMEOUT, &[0]) };
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn query_option() {
+        use winapi::um::wininet::{INTERNET_OPTION_CONNECT_TIMEOUT, INTERNET_OPTION_USER_AGENT};
+
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        internet
+            .set_option_dword(INTERNET_OPTION_CONNECT_TIMEOUT, 4321)
+            .unwrap();
+        assert_eq!(
+            internet
+                .query_option_dword(INTERNET_OPTION_CONNECT_TIMEOUT)
+                .unwrap(),
+            4321
+        );
+        let agent = internet.query_option(INTERNET_OPTION_USER_AGENT).unwrap();
+        let agent: Vec<u16> = agent
+            .chunks_exact(2)
+            .map(|pair| u16::from_ne_bytes([pair[0], pair[1]]))
+            .collect();
+        assert_eq!(
+            String::from_utf16_lossy(&agent).trim_end_matches('\0'),
+            "agent"
+        );
+    }
+
+    #[test]
+    fn proxy_info() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        assert!(internet.proxy_info().unwrap().direct);
+        let internet =
+            wininet::Internet::open_with_proxy("agent", "127.0.0.1:8888", Some("<local>")).unwrap();
+        assert_eq!(
+            internet.proxy_info().unwrap(),
+            wininet::ProxyInfo {
+                direct: false,
+                proxy: Some("127.0.0.1:8888".to_string()),
+                bypass: Some("<local>".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn http_version() {
+        use wininet::HttpVersion;
+
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.get("http://httpbin.org/get", None).unwrap();
+        assert_eq!(response.http_version(), Some(HttpVersion::Http1_1));
+        let expected = if internet.enable_http2() {
+            HttpVersion::Http2
+        } else {
+            HttpVersion::Http1_1
+        };
+        let response = internet.get("https://httpbin.org/get", None).unwrap();
+        assert_eq!(response.http_version(), Some(expected));
+    }
+
+    #[test]
+    fn max_redirects() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .request_builder("GET", "http://httpbin.org/redirect/3")
+            .max_redirects(3)
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let result = internet
+            .request_builder("GET", "http://httpbin.org/redirect/3")
+            .max_redirects(2)
+            .send();
+        match result {
+            Err(wininet::HttpError::TooManyRedirects { urls }) => assert_eq!(urls.len(), 3),
+            _ => panic!("expected TooManyRedirects"),
+        }
+
+        // A 302 turns the POST into a GET; a 307 resends it.
+        let post = |status| {
+            let url = format!(
+                "http://httpbin.org/redirect-to?url=/anything&status_code={}",
+                status
+            );
+            let response = internet
+                .request_builder("POST", &url)
+                .body(b"kept")
+                .max_redirects(1)
+                .send()
+                .unwrap();
+            String::from_utf8(response.read_to_vec().unwrap()).unwrap()
+        };
+        let redirected = post(302);
+        assert!(redirected.contains("\"method\": \"GET\""));
+        assert!(!redirected.contains("Content-Type"), "{}", redirected);
+        let resent = post(307);
+        assert!(resent.contains("\"method\": \"POST\"") && resent.contains("kept"));
+
+        // Credentials follow a redirect on the same origin but not to another.
+        let echoed = |target: &str| {
+            let url = format!("http://httpbin.org/redirect-to?url={}", target);
+            let response = internet
+                .request_builder("GET", &url)
+                .basic_auth("user", Some("passwd"))
+                .header("Cookie", "session=secret")
+                .max_redirects(1)
+                .send()
+                .unwrap();
+            response.text().unwrap()
+        };
+        let same = echoed("/anything");
+        assert!(same.contains("\"Authorization\"") && same.contains("session=secret"));
+        let other = echoed("https://httpbin.org/anything");
+        assert!(!other.contains("\"Authorization\""), "{}", other);
+        assert!(!other.contains("session=secret"), "{}", other);
+    }
+
+    #[cfg(feature = "url")]
+    #[test]
+    fn get_url() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let url = url::Url::parse("http://httpbin.org/anything/a%20b?x=1&y=2#fragment").unwrap();
+        let response = internet.get_url(&url, Some("X-Foo: bar")).unwrap();
+        assert_eq!(response.status(), 200);
+        let body = response.text().unwrap();
+        assert!(
+            body.contains("\"url\": \"http://httpbin.org/anything/a%20b?x=1&y=2\""),
+            "{}",
+            body
+        );
+        assert!(body.contains("\"X-Foo\": \"bar\""));
+
+        let url = url::Url::parse("ftp://example.com/").unwrap();
+        assert!(internet.get_url(&url, None).is_err());
+    }
+
+    #[test]
+    fn header_append() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let body = internet
+            .request_builder("GET", "http://httpbin.org/headers")
+            .header("X-Repeated", "replaced")
+            .header("X-Repeated", "first")
+            .header_append("X-Repeated", "second")
+            .send()
+            .unwrap()
+            .read_to_vec()
+            .unwrap();
+        // httpbin folds repeated lines into one comma-separated value.
+        let body = String::from_utf8(body).unwrap();
+        assert!(body.contains("first,second"), "{}", body);
+        assert!(!body.contains("replaced"));
+    }
+
+    #[test]
+    fn bodiless_responses_are_complete() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.head("http://httpbin.org/bytes/100", None).unwrap();
+        assert_eq!(response.content_length(), Some(100));
+        assert!(response.read_to_vec().unwrap().is_empty());
+        let response = internet.get("http://httpbin.org/status/204", None).unwrap();
+        assert!(response.read_to_vec().unwrap().is_empty());
+    }
+
+    #[test]
+    fn read_partial() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let start = Instant::now();
+        let response = internet
+            .request_builder(
+                "GET",
+                "http://httpbin.org/drip?duration=10&numbytes=10&delay=0",
+            )
+            .send_with_deadline(start + Duration::from_secs(4))
+            .unwrap();
+        let (body, result) = response.read_partial();
+        assert!(!body.is_empty() && body.len() < 10);
+        assert!(body.iter().all(|&byte| byte == b'*'));
+        assert_eq!(
+            wininet::HttpError::from(result.unwrap_err()).code(),
+            winapi::um::wininet::ERROR_INTERNET_TIMEOUT
+        );
+    }
+
+    #[test]
+    fn body_from_file() {
+        let path = std::env::temp_dir().join("thin-http-body_from_file.txt");
+        std::fs::write(&path, "uploaded").unwrap();
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let body = internet
+            .request_builder("PUT", "http://httpbin.org/put")
+            .body_from_file(&path)
+            .send()
+            .unwrap()
+            .text()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(body.contains("\"data\": \"uploaded\""));
+        assert!(body.contains("\"Content-Type\": \"text/plain\""));
+        assert!(body.contains("\"Content-Length\": \"8\""));
+
+        let result = internet
+            .request_builder("PUT", "http://httpbin.org/put")
+            .body_from_file(&path)
+            .send();
+        assert!(matches!(result, Err(wininet::HttpError::Io(_))));
+    }
+
+    #[test]
+    fn expect_continue() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let body = vec![b'x'; 64 * 1024];
+        let response = internet
+            .request_builder("PUT", "http://httpbin.org/status/417")
+            .expect_continue()
+            .body_from_reader(&body[..], Some(body.len() as u64))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 417);
+    }
+
+    #[test]
+    fn header_as() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .get(
+                "http://httpbin.org/response-headers?X-Remaining=%2042%20&X-Name=abc",
+                None,
+            )
+            .unwrap();
+        assert_eq!(response.header_as::<u32>("X-Remaining"), Some(42));
+        assert_eq!(response.header_as::<u32>("X-Name"), None);
+        assert_eq!(response.header_as::<u32>("X-Missing"), None);
+    }
+
+    #[test]
+    fn retry_after() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let retry_after = |value: &str| {
+            let url = format!(
+                "http://httpbin.org/response-headers?Retry-After={}",
+                uri::encode_uri_component(value)
+            );
+            internet.get(&url, None).unwrap().retry_after()
+        };
+        assert_eq!(retry_after("120"), Some(Duration::from_secs(120)));
+        let later = http_date::format(std::time::SystemTime::now() + Duration::from_secs(600));
+        let wait = retry_after(&later).unwrap();
+        assert!(wait > Duration::from_secs(590) && wait <= Duration::from_secs(600));
+        assert_eq!(
+            retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after("soon"), None);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn decoded_reader() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        for (url, marker) in [
+            ("http://httpbin.org/gzip", "\"gzipped\": true"),
+            ("http://httpbin.org/deflate", "\"deflated\": true"),
+            ("http://httpbin.org/get", "\"url\""),
+        ] {
+            let response = internet.get(url, None).unwrap();
+            let mut body = String::new();
+            response
+                .decoded_reader()
+                .unwrap()
+                .read_to_string(&mut body)
+                .unwrap();
+            assert!(body.contains(marker), "{}", body);
+        }
+    }
+
+    #[test]
+    fn into_reader() {
+        fn open(internet: &wininet::Internet) -> wininet::ResponseReader {
+            internet
+                .get("http://httpbin.org/bytes/100", None)
+                .unwrap()
+                .into_reader()
+        }
+
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let mut reader = open(&internet);
+        assert_eq!(reader.response().status(), 200);
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).unwrap();
+        assert_eq!(body.len(), 100);
+    }
+
+    #[test]
+    fn proxy_auth_required() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.get("http://httpbin.org/status/407", None).unwrap();
+        assert!(response.is_proxy_auth_required());
+        let challenges = response.proxy_authenticate();
+        assert!(challenges
+            .iter()
+            .any(|challenge| challenge.starts_with("Basic")));
+        match response.error_for_status() {
+            Err(error @ wininet::HttpError::ProxyAuthRequired { .. }) => {
+                assert_eq!(error.status(), Some(407))
+            }
+            _ => panic!("expected ProxyAuthRequired"),
+        }
+    }
+
+    #[test]
+    fn challenge_parse() {
+        let challenge: wininet::Challenge =
+            r#"Bearer realm="x, y", error=invalid_token,scope="a \"b\"""#
+                .parse()
+                .unwrap();
+        assert_eq!(challenge.scheme, "Bearer");
+        assert_eq!(challenge.token, None);
+        assert_eq!(challenge.param("Realm"), Some("x, y"));
+        assert_eq!(challenge.param("error"), Some("invalid_token"));
+        assert_eq!(challenge.param("scope"), Some(r#"a "b""#));
+
+        let challenge: wininet::Challenge = "Negotiate YIIB3wYGKwYBBQUCoIIB0zCC==".parse().unwrap();
+        assert_eq!(
+            challenge.token.as_deref(),
+            Some("YIIB3wYGKwYBBQUCoIIB0zCC==")
+        );
+        assert!(challenge.params.is_empty());
+        let challenge: wininet::Challenge = "Basic".parse().unwrap();
+        assert_eq!(challenge.scheme, "Basic");
+        assert!(r#"Digest realm="open"#.parse::<wininet::Challenge>().is_err());
+    }
+
+    #[test]
+    fn www_authenticate() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet.get("http://httpbin.org/bearer", None).unwrap();
+        assert_eq!(response.status(), 401);
+        let challenges = response.www_authenticate();
+        let challenge: wininet::Challenge = challenges[0].parse().unwrap();
+        assert_eq!(challenge.scheme, "Bearer");
+    }
+
+    #[test]
+    fn digest_auth() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        for algorithm in ["MD5", "SHA-256"] {
+            let url = format!(
+                "http://httpbin.org/digest-auth/auth/user/passwd/{}",
+                algorithm
+            );
+            let send = |pass| {
+                internet
+                    .request_builder("GET", &url)
+                    .digest_auth("user", pass)
+                    .send()
+                    .unwrap()
+                    .status()
+            };
+            assert_eq!(send("passwd"), 200, "{}", algorithm);
+            assert_eq!(send("wrong"), 401, "{}", algorithm);
+        }
+    }
+
+    #[test]
+    fn one_shot_post() {
+        let response = wininet::post("http://httpbin.org/post", b"one shot").unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(response.text().unwrap().contains("one shot"));
+    }
+
+    #[test]
+    fn default_agent() {
+        let agent = wininet::default_agent("myapp", "1.2.3");
+        assert!(agent.starts_with("myapp/1.2.3 (Windows NT "), "{}", agent);
+        let internet = wininet::Internet::open(&agent, None).unwrap();
+        let response = internet.get("http://httpbin.org/user-agent", None).unwrap();
+        assert!(response.text().unwrap().contains(&agent));
+    }
+
+    #[test]
+    fn for_each_chunk() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let response = internet
+            .get("http://httpbin.org/stream-bytes/5000?chunk_size=1000", None)
+            .unwrap();
+        let mut total = 0;
+        response
+            .for_each_chunk(|chunk| {
+                total += chunk.len();
+                Ok(())
+            })
+            .unwrap();
+        assert!(total >= 5000);
+
+        let response = internet
+            .get("http://httpbin.org/bytes/10000", None)
+            .unwrap();
+        let mut calls = 0;
+        let error = response
+            .for_each_chunk(|_| {
+                calls += 1;
+                Err(std::io::Error::other("stop"))
+            })
+            .unwrap_err();
+        assert_eq!(error.to_string(), "stop");
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn secure_flag_follows_scheme() {
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        for (url, secure) in [
+            ("http://httpbin.org/get", false),
+            ("https://httpbin.org/get", true),
+            ("HTTPS://httpbin.org/get", true),
+        ] {
+            let response = internet.get_no_redirect(url, None).unwrap();
+            assert_eq!(response.status(), 200, "{}", url);
+            assert_eq!(response.is_secure(), secure, "{}", url);
+
+            let response = internet.request_builder("GET", url).send().unwrap();
+            assert_eq!(response.status(), 200, "{}", url);
+            assert_eq!(response.is_secure(), secure, "{}", url);
+        }
+        let connection = internet.connect_with(wininet::Scheme::Http, "httpbin.org", None);
+        let response = connection
+            .unwrap()
+            .request("GET", "/get", None, None)
+            .unwrap();
+        assert!(!response.is_secure());
+        let connection = internet.connect_with(wininet::Scheme::Https, "httpbin.org", None);
+        let response = connection
+            .unwrap()
+            .request("GET", "/get", None, None)
+            .unwrap();
+        assert!(response.is_secure());
+    }
+
+    #[test]
+    fn chunked_body() {
+        use std::io::Write;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 4096];
+            let _ = stream.read(&mut request).unwrap();
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTrailer: X-Checksum\r\n\
+                      Connection: close\r\n\r\n\
+                      5\r\nhello\r\n7\r\n, world\r\n0\r\nX-Checksum: abc\r\n\r\n",
+                )
+                .unwrap();
+        });
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let url = format!("http://127.0.0.1:{}/", port);
+        let response = internet.get(&url, None).unwrap();
+        assert_eq!(response.content_length(), None);
+        assert_eq!(response.text().unwrap(), "hello, world");
+        // WinINet removed the framing, and the trailers with it.
+        assert!(response.trailers().is_empty());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn trailers() {
+        use std::io::Write;
+        use winapi::um::wininet::INTERNET_FLAG_RAW_DATA;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 4096];
+            let _ = stream.read(&mut request).unwrap();
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTrailer: X-Checksum\r\n\
+                      Connection: close\r\n\r\n\
+                      5\r\nhello\r\n7\r\n, world\r\n0\r\nX-Checksum: abc\r\n\r\n",
+                )
+                .unwrap();
+        });
+        let internet = wininet::Internet::open("agent", None).unwrap();
+        let url = format!("http://127.0.0.1:{}/", port);
+        let response = internet
+            .get_with_flags(&url, None, INTERNET_FLAG_RAW_DATA)
+            .unwrap();
+        assert!(response.trailers().is_empty());
+        assert_eq!(response.text().unwrap(), "hello, world");
+        assert_eq!(
+            response.trailers(),
+            [("X-Checksum".to_string(), "abc".to_string())]
+        );
+        server.join().unwrap();
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_read_dropped_midway() {
+        use std::future::Future;
+        use std::sync::Arc;
+        use std::task::{Context, Poll, Wake};
+
+        struct Unpark(std::thread::Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        fn block_on<F: Future>(future: F) -> F::Output {
+            let waker = Arc::new(Unpark(std::thread::current())).into();
+            let mut context = Context::from_waker(&waker);
+            let mut future = Box::pin(future);
+            loop {
+                match future.as_mut().poll(&mut context) {
+                    Poll::Ready(output) => return output,
+                    Poll::Pending => std::thread::park(),
+                }
+            }
+        }
+
+        let internet = wininet::asynchronous::AsyncInternet::open("agent").unwrap();
+        let mut response = block_on(internet.get(
+            "http://httpbin.org/drip?numbytes=2000&duration=2&delay=0",
+            None,
+        ))
+        .unwrap();
+        // Start a large read, give up on it while it is still in flight,
+        // then carry on with a smaller buffer.
+        let mut large = vec![0u8; 64 * 1024];
+        let waker = Arc::new(Unpark(std::thread::current())).into();
+        let mut context = Context::from_waker(&waker);
+        let mut total = {
+            let mut read = Box::pin(response.read(&mut large));
+            match read.as_mut().poll(&mut context) {
+                Poll::Ready(read) => read.unwrap(),
+                Poll::Pending => 0,
+            }
+        };
+        drop(large);
+        let mut small = [0u8; 100];
+        loop {
+            match block_on(response.read(&mut small)).unwrap() {
+                0 => break,
+                read => total += read,
+            }
+        }
+        assert_eq!(total, 2000);
+    }
+
+    #[test]
+    fn digest_nonce_reused() {
+        use std::sync::{Arc, Mutex};
+
+        let headers = Arc::new(Mutex::new(Vec::new()));
+        let sink = headers.clone();
+        let mut internet = wininet::Internet::open("agent", None).unwrap();
+        let logger = wininet::Logger::new(move |event| {
+            if let wininet::LogEvent::Request { headers, .. } = event {
+                sink.lock().unwrap().push(headers.to_string());
+            }
+        });
+        internet.set_logger(logger.with_secrets(true));
+        let connection = internet.connect("httpbin.org", 80).unwrap();
+        for _ in 0..2 {
+            let response = connection
+                .request_builder("GET", "/digest-auth/auth/user/passwd/MD5")
+                .digest_auth("user", "passwd")
+                .send()
+                .unwrap();
+            assert_eq!(response.status(), 200);
+        }
+        // One 401 for the first request, then both answered with one nonce.
+        let headers = headers.lock().unwrap().clone();
+        assert_eq!(headers.len(), 3, "{:?}", headers);
+        assert!(!headers[0].contains("Authorization"));
+        let nonce = |headers: &str| {
+            let start = headers.find(" nonce=\"").unwrap() + 8;
+            headers[start..start + headers[start..].find('"').unwrap()].to_string()
+        };
+        assert_eq!(nonce(&headers[1]), nonce(&headers[2]));
+        assert!(headers[1].contains("nc=00000001"), "{}", headers[1]);
+        assert!(headers[2].contains("nc=00000002"), "{}", headers[2]);
     }
 }